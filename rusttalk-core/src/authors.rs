@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use loro::{LoroDoc, LoroValue, PeerID};
+
+use crate::awareness::IdBytes;
+use crate::profile::Profile;
+
+/// Root map from Loro peer id to the user behind it. Every session run edits with a
+/// fresh peer id, so each one is recorded when it starts and history can still name
+/// authors long after they went offline.
+const AUTHORS_MAP: &str = "authors";
+
+/// The user who made the changes of one Loro peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Author {
+    pub name: String,
+    pub endpoint_id: Option<IdBytes>,
+}
+
+/// Records our own Loro peer under the endpoint and name we join with.
+pub(crate) fn record(loro_doc: &LoroDoc, endpoint_id: IdBytes, profile: &Profile) -> Result<()> {
+    let author = HashMap::from([
+        ("name", LoroValue::from(profile.name.as_str())),
+        ("endpoint_id", LoroValue::from(endpoint_id.to_vec())),
+    ]);
+    loro_doc
+        .get_map(AUTHORS_MAP)
+        .insert(&loro_doc.peer_id().to_string(), author)?;
+    loro_doc.commit();
+
+    Ok(())
+}
+
+/// Who made the changes of `peer`, if that peer recorded itself. Builds from before
+/// this map existed never did.
+pub fn author(loro_doc: &LoroDoc, peer: PeerID) -> Option<Author> {
+    let value = loro_doc
+        .get_map(AUTHORS_MAP)
+        .get(&peer.to_string())?
+        .into_value()
+        .ok()?;
+    let LoroValue::Map(fields) = value else {
+        return None;
+    };
+
+    let Some(LoroValue::String(name)) = fields.get("name") else {
+        return None;
+    };
+    let endpoint_id = match fields.get("endpoint_id") {
+        Some(LoroValue::Binary(bytes)) => <IdBytes>::try_from(bytes.as_slice()).ok(),
+        _ => None,
+    };
+
+    Some(Author {
+        name: name.to_string(),
+        endpoint_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_author_reaches_other_peers() {
+        let loro_doc = LoroDoc::new();
+        record(&loro_doc, [7; 32], &Profile::new("Ada".to_string())).unwrap();
+
+        let other_doc = LoroDoc::new();
+        other_doc
+            .import(&loro_doc.export(loro::ExportMode::all_updates()).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            author(&other_doc, loro_doc.peer_id()),
+            Some(Author {
+                name: "Ada".to_string(),
+                endpoint_id: Some([7; 32]),
+            })
+        );
+        assert_eq!(author(&other_doc, other_doc.peer_id()), None);
+    }
+}
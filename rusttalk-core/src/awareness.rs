@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use loro::PeerID;
+use loro::cursor::Cursor;
+use postcard::{from_bytes, to_allocvec as to_bytes};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::gossip_message::GossipMessage;
+use crate::profile::Profile;
+use crate::session::{Presence, SessionContext, SessionEvent};
+use crate::workspace::{DocumentId, MAIN_DOCUMENT};
+
+const CACHE_TTL: Duration = Duration::from_secs(5);
+/// Edits this recent make us show up as typing.
+const TYPING_WINDOW: Duration = Duration::from_secs(2);
+/// Without any input for this long we show up as idle.
+const IDLE_AFTER: Duration = Duration::from_secs(60);
+/// How long a "left" notice stays in [`Presence::recently_left`].
+const LEFT_NOTICE_TTL: Duration = Duration::from_secs(5);
+
+pub type IdBytes = [u8; 32];
+pub type AwarenessCache = HashMap<IdBytes, (Awareness, Instant)>;
+pub type LoroCursors = Option<(Cursor, Cursor)>;
+
+/// On the wire only the fields of protocol version 1 live in the struct itself; the
+/// rest travel as [extensions](Awareness::extensions) behind the message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(into = "AwarenessWire", from = "AwarenessWire")]
+pub struct Awareness {
+    pub endpoint_id: IdBytes,
+    pub loro_peer_id: PeerID,
+    pub profile: Profile,
+    pub loro_cursors: Option<(Cursor, Cursor)>,
+    /// Encoded oplog `VersionVector`, acknowledging what the sender has received.
+    pub version: Vec<u8>,
+    pub timestamp_ms: u64,
+    pub activity: Activity,
+    /// First character visible in the sender's editor, so others can follow along.
+    pub viewport: Option<Cursor>,
+    /// Workspace document the sender has open; cursors and viewport point into it.
+    pub document: DocumentId,
+}
+
+/// The awareness layout of protocol version 1. Frozen, so every build can read it.
+#[derive(Serialize, Deserialize)]
+struct AwarenessWire {
+    endpoint_id: IdBytes,
+    loro_peer_id: PeerID,
+    name: String,
+    loro_cursors: Option<(Cursor, Cursor)>,
+    version: Vec<u8>,
+    timestamp_ms: u64,
+}
+
+impl From<Awareness> for AwarenessWire {
+    fn from(awareness: Awareness) -> Self {
+        Self {
+            endpoint_id: awareness.endpoint_id,
+            loro_peer_id: awareness.loro_peer_id,
+            name: awareness.profile.name,
+            loro_cursors: awareness.loro_cursors,
+            version: awareness.version,
+            timestamp_ms: awareness.timestamp_ms,
+        }
+    }
+}
+
+impl From<AwarenessWire> for Awareness {
+    fn from(wire: AwarenessWire) -> Self {
+        Self {
+            endpoint_id: wire.endpoint_id,
+            loro_peer_id: wire.loro_peer_id,
+            profile: Profile::new(wire.name),
+            loro_cursors: wire.loro_cursors,
+            version: wire.version,
+            timestamp_ms: wire.timestamp_ms,
+            activity: Activity::default(),
+            viewport: None,
+            document: MAIN_DOCUMENT.to_string(),
+        }
+    }
+}
+
+impl Awareness {
+    /// Fields added after protocol version 1, each encoded on its own and in the
+    /// order they were added. New fields go at the end.
+    pub(crate) fn extensions(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(vec![
+            to_bytes(&self.profile)?,
+            to_bytes(&self.activity)?,
+            to_bytes(&self.viewport)?,
+            to_bytes(&self.document)?,
+        ])
+    }
+
+    /// Fills in the extensions an older sender knew about. Missing ones keep their
+    /// defaults and ones added after this build are ignored.
+    pub(crate) fn apply_extensions(&mut self, extensions: &[Vec<u8>]) {
+        if let Some(profile) = extension(extensions, 0) {
+            self.profile = profile;
+        }
+        if let Some(activity) = extension(extensions, 1) {
+            self.activity = activity;
+        }
+        if let Some(viewport) = extension(extensions, 2) {
+            self.viewport = viewport;
+        }
+        if let Some(document) = extension(extensions, 3) {
+            self.document = document;
+        }
+    }
+}
+
+/// Extension at `index`, unless it is missing or was encoded in a shape this build
+/// cannot read, such as a newer `Activity`.
+fn extension<T: DeserializeOwned>(extensions: &[Vec<u8>], index: usize) -> Option<T> {
+    from_bytes(extensions.get(index)?).ok()
+}
+
+/// What a user is doing right now, as shown next to their name and caret.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Activity {
+    #[default]
+    Active,
+    /// Edited the document within the last couple of seconds.
+    Typing,
+    /// No input for a while.
+    Idle,
+    /// The window is not focused.
+    Away,
+}
+
+impl Activity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Activity::Active => "active",
+            Activity::Typing => "typing",
+            Activity::Idle => "idle",
+            Activity::Away => "away",
+        }
+    }
+}
+
+/// Local input the own [`Activity`] is derived from.
+#[derive(Debug, Clone)]
+pub struct LocalActivity {
+    pub focused: bool,
+    last_input: Instant,
+    last_edit: Option<Instant>,
+}
+
+impl Default for LocalActivity {
+    fn default() -> Self {
+        Self {
+            focused: true,
+            last_input: Instant::now(),
+            last_edit: None,
+        }
+    }
+}
+
+impl LocalActivity {
+    pub fn record_input(&mut self, edited: bool) {
+        let now = Instant::now();
+        self.last_input = now;
+        if edited {
+            self.last_edit = Some(now);
+        }
+    }
+
+    pub fn activity(&self) -> Activity {
+        if !self.focused {
+            Activity::Away
+        } else if self
+            .last_edit
+            .is_some_and(|last_edit| last_edit.elapsed() < TYPING_WINDOW)
+        {
+            Activity::Typing
+        } else if self.last_input.elapsed() >= IDLE_AFTER {
+            Activity::Idle
+        } else {
+            Activity::Active
+        }
+    }
+}
+
+/// Short printable form of an endpoint id, for logs and tooltips.
+pub fn short_id(id_bytes: &IdBytes) -> String {
+    match iroh::EndpointId::from_bytes(id_bytes) {
+        Ok(endpoint_id) => endpoint_id.fmt_short().to_string(),
+        Err(_) => "unknown peer".to_string(),
+    }
+}
+
+/// A peer that announced it left the session.
+#[derive(Debug, Clone)]
+pub struct DepartedPeer {
+    pub endpoint_id: IdBytes,
+    pub profile: Profile,
+    /// Sender's clock when it left, so stale awareness cannot bring it back.
+    pub timestamp_ms: u64,
+    pub received_at: Instant,
+}
+
+pub(crate) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub(crate) fn awareness_refresh(ctx: &SessionContext) -> Result<()> {
+    broadcast_awareness(ctx)?;
+
+    let instant_now = Instant::now();
+    let expired = {
+        let mut presence = ctx.presence.lock();
+        let before = presence.awareness_cache.len();
+        presence
+            .awareness_cache
+            .retain(|_, (_, received_at)| instant_now.duration_since(*received_at) < CACHE_TTL);
+        let expired = presence.awareness_cache.len() != before;
+
+        // Peers that went away without a leave notice, whether or not we could
+        // read their awareness
+        let before = presence.peer_protocols.len();
+        presence
+            .peer_protocols
+            .retain(|_, (_, received_at)| instant_now.duration_since(*received_at) < CACHE_TTL);
+        let expired = expired || presence.peer_protocols.len() != before;
+
+        let before = presence.recently_left.len();
+        presence
+            .recently_left
+            .retain(|departed| instant_now.duration_since(departed.received_at) < LEFT_NOTICE_TTL);
+        expired || presence.recently_left.len() != before
+    };
+
+    if expired {
+        ctx.emit(SessionEvent::PresenceChanged);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn broadcast_awareness(ctx: &SessionContext) -> Result<()> {
+    let timestamp_now = unix_time_ms();
+    let (loro_cursors, activity, viewport, document) = {
+        let presence = ctx.presence.lock();
+        (
+            presence.own_cursors.clone(),
+            presence.own_activity.activity(),
+            presence.own_viewport.clone(),
+            presence.own_document.clone(),
+        )
+    };
+    ctx.outbound_queue
+        .send(GossipMessage::Awareness(Box::new(Awareness {
+            endpoint_id: ctx.own_id,
+            loro_peer_id: ctx.loro_doc.peer_id(),
+            profile: ctx.own_profile.clone(),
+            loro_cursors,
+            version: ctx.loro_doc.oplog_vv().encode(),
+            timestamp_ms: timestamp_now,
+            activity,
+            viewport,
+            document,
+        })))?;
+
+    Ok(())
+}
+
+pub(crate) fn update_awareness_cache(
+    presence: &mut Presence,
+    own_id: IdBytes,
+    awareness: Awareness,
+) {
+    if awareness.endpoint_id == own_id {
+        return;
+    }
+
+    // Awareness sent before a leave can still arrive after it
+    let left_since = presence.recently_left.iter().any(|departed| {
+        departed.endpoint_id == awareness.endpoint_id
+            && departed.timestamp_ms >= awareness.timestamp_ms
+    });
+    if left_since {
+        return;
+    }
+
+    let old_entry = presence.awareness_cache.get(&awareness.endpoint_id);
+    let should_update = if let Some((existing, _)) = old_entry {
+        awareness.timestamp_ms > existing.timestamp_ms
+    } else {
+        true
+    };
+
+    if should_update {
+        presence
+            .awareness_cache
+            .insert(awareness.endpoint_id, (awareness, Instant::now()));
+    }
+}
+
+/// Drops a peer that said it is leaving and remembers it for the "left" notice.
+/// Returns whether the peer was present.
+pub(crate) fn remove_departed(
+    presence: &mut Presence,
+    endpoint_id: IdBytes,
+    timestamp_ms: u64,
+) -> bool {
+    presence.peer_protocols.remove(&endpoint_id);
+    let Some((awareness, _)) = presence.awareness_cache.remove(&endpoint_id) else {
+        return false;
+    };
+
+    presence.recently_left.push(DepartedPeer {
+        endpoint_id,
+        profile: awareness.profile,
+        timestamp_ms,
+        received_at: Instant::now(),
+    });
+
+    true
+}
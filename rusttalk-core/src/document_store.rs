@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use iroh::EndpointAddr;
+use iroh_gossip::TopicId;
+use postcard::{from_bytes, to_allocvec as to_bytes};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    session::SessionContext,
+    session_crypto::SessionSecret,
+    storage::{Access, write_atomic},
+};
+
+const MAX_RECENT_DOCUMENTS: usize = 10;
+const MAX_TITLE_CHARS: usize = 40;
+
+/// Entry in the lobby's list of recently opened documents.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecentDocument {
+    pub topic_id: TopicId,
+    pub secret: SessionSecret,
+    pub title: String,
+    pub peers: Vec<EndpointAddr>,
+    pub last_opened_ms: u64,
+}
+
+fn data_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .context("No data directory available")?
+        .join("rusty-collab");
+    fs::create_dir_all(dir.join("documents"))?;
+
+    Ok(dir)
+}
+
+fn document_path(dir: &Path, topic_id: &TopicId) -> PathBuf {
+    dir.join("documents").join(format!("{topic_id}.loro"))
+}
+
+pub fn load_recent_documents() -> Vec<RecentDocument> {
+    data_dir()
+        .and_then(|dir| Ok(fs::read(dir.join("recent.bin"))?))
+        .ok()
+        .and_then(|bytes| from_bytes(&bytes).ok())
+        .unwrap_or_default()
+}
+
+pub fn load_document(topic_id: &TopicId) -> Result<Option<Vec<u8>>> {
+    let path = document_path(&data_dir()?, topic_id);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(fs::read(path)?))
+}
+
+/// Exports a snapshot of the session's document if it changed since the last save,
+/// and moves the document to the top of the recent documents list.
+pub(crate) fn save_document(ctx: &SessionContext) -> Result<()> {
+    let version = ctx.loro_doc.oplog_vv();
+    if version == *ctx.saved_version.lock() {
+        return Ok(());
+    }
+
+    let dir = data_dir()?;
+    let snapshot = ctx.loro_doc.export(loro::ExportMode::Snapshot)?;
+    // Session contents are meant for the session's members only
+    write_atomic(
+        &document_path(&dir, &ctx.topic_id),
+        &snapshot,
+        Access::Private,
+    )?;
+    *ctx.saved_version.lock() = version;
+
+    let text = ctx.loro_doc.get_text("text").to_string();
+    let title = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(|line| line.chars().take(MAX_TITLE_CHARS).collect())
+        .unwrap_or_else(|| "Untitled".to_string());
+
+    let mut recent_documents = load_recent_documents();
+    recent_documents.retain(|doc| doc.topic_id != ctx.topic_id);
+    recent_documents.insert(
+        0,
+        RecentDocument {
+            topic_id: ctx.topic_id,
+            secret: ctx.secret,
+            title,
+            peers: ctx.ticket().peers,
+            last_opened_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        },
+    );
+    recent_documents.truncate(MAX_RECENT_DOCUMENTS);
+    // Holds every session's secret, which is all it takes to join and decrypt
+    write_atomic(
+        &dir.join("recent.bin"),
+        &to_bytes(&recent_documents)?,
+        Access::Private,
+    )?;
+
+    Ok(())
+}
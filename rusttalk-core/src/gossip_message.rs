@@ -0,0 +1,360 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use iroh::EndpointId;
+use loro::{LoroDoc, VersionVector};
+use parking_lot::Mutex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::awareness;
+use crate::awareness::{Awareness, IdBytes};
+use crate::protocol::Features;
+use crate::session::{OutboundQueue, Presence, SessionContext, SessionEvent};
+use crate::sync_protocol::task_direct_sync;
+
+/// How many peers answer a single `RequestData`.
+const RESPONDERS_PER_REQUEST: usize = 2;
+
+/// Larger replies are offered over the direct sync protocol instead, keeping well
+/// clear of the gossip message size limit.
+const MAX_INLINE_UPDATE_SIZE: usize = 1024 * 1024;
+
+/// How long to wait before asking again while a peer's awareness shows updates we
+/// lack. Those usually arrive on their own a moment later.
+const REQUEST_RETRY_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, Deserialize)]
+pub enum GossipMessage {
+    /// Asks peers for every update missing from the encoded `VersionVector`.
+    RequestData {
+        endpoint_id: IdBytes,
+        version: Vec<u8>,
+    },
+    Update {
+        data: Vec<u8>,
+    },
+    Awareness(Box<Awareness>),
+    /// Tells the requester to fetch its missing updates directly from the responder.
+    DirectSyncOffer {
+        requester_id: IdBytes,
+        responder_id: IdBytes,
+    },
+    /// The sender is leaving the session and should be dropped from presence now
+    /// rather than when its awareness expires.
+    Leave {
+        endpoint_id: IdBytes,
+        timestamp_ms: u64,
+    },
+}
+
+impl GossipMessage {
+    /// Fields added to this message after protocol version 1; see
+    /// [`Envelope`](crate::protocol::Envelope).
+    pub(crate) fn extensions(&self) -> Result<Vec<Vec<u8>>> {
+        match self {
+            GossipMessage::Awareness(awareness) => awareness.extensions(),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    pub(crate) fn apply_extensions(&mut self, extensions: &[Vec<u8>]) {
+        if let GossipMessage::Awareness(awareness) = self {
+            awareness.apply_extensions(extensions);
+        }
+    }
+
+    pub fn request_data(own_id: IdBytes, loro_doc: &LoroDoc) -> Self {
+        GossipMessage::RequestData {
+            endpoint_id: own_id,
+            version: loro_doc.oplog_vv().encode(),
+        }
+    }
+}
+
+/// Elects the peers whose ids are XOR-closest to the requester's, among the peers
+/// we know from awareness, so only a few of them reply to a request.
+fn is_elected_responder<'a>(
+    known_peers: impl Iterator<Item = &'a IdBytes>,
+    own_id: &IdBytes,
+    requester_id: &IdBytes,
+) -> bool {
+    let distance = |id: &IdBytes| -> IdBytes { std::array::from_fn(|i| id[i] ^ requester_id[i]) };
+    let own_distance = distance(own_id);
+
+    let closer_peers = known_peers
+        .filter(|id| *id != requester_id && distance(id) < own_distance)
+        .count();
+
+    closer_peers < RESPONDERS_PER_REQUEST
+}
+
+fn supports(presence: &Presence, peer: &IdBytes, features: Features) -> bool {
+    presence
+        .peer_protocols
+        .get(peer)
+        .is_some_and(|(protocol, _)| protocol.features.contains(features))
+}
+
+/// Has each of `peers` that can pull over the direct sync protocol fetch its missing
+/// updates from us, for updates too big for gossip.
+fn offer_direct_sync(
+    outbound_queue: &OutboundQueue,
+    presence: &Presence,
+    own_id: IdBytes,
+    peers: impl Iterator<Item = IdBytes>,
+) {
+    for peer in peers {
+        if !supports(presence, &peer, Features::DIRECT_SYNC) {
+            eprintln!(
+                "{} cannot fetch large updates over direct sync",
+                awareness::short_id(&peer)
+            );
+            continue;
+        }
+        let _ = outbound_queue.send(GossipMessage::DirectSyncOffer {
+            requester_id: peer,
+            responder_id: own_id,
+        });
+    }
+}
+
+/// Asks the session for every update we are missing.
+pub(crate) fn request_missing(ctx: &SessionContext) {
+    *ctx.last_data_request.lock() = Some(Instant::now());
+    let _ = ctx
+        .outbound_queue
+        .send(GossipMessage::request_data(ctx.own_id, &ctx.loro_doc));
+}
+
+/// Whether a peer at `their_version` has updates we lack and we last asked long
+/// enough ago. Only the elected responders answer a request, and they may be behind
+/// themselves or gone, so without asking again a joiner could wait forever.
+fn should_request_again(
+    their_version: &VersionVector,
+    own_version: &VersionVector,
+    last_request: Option<Instant>,
+) -> bool {
+    !own_version.includes_vv(their_version)
+        && last_request.is_none_or(|at| at.elapsed() >= REQUEST_RETRY_INTERVAL)
+}
+
+/// Sends an update made locally to the session. One too big for gossip, such as a
+/// large paste, goes out as a direct sync offer to every peer we have heard from
+/// instead.
+pub(crate) fn send_local_update(
+    outbound_queue: &OutboundQueue,
+    presence: &Mutex<Presence>,
+    own_id: IdBytes,
+    data: &[u8],
+) {
+    if data.len() <= MAX_INLINE_UPDATE_SIZE {
+        let _ = outbound_queue.send(GossipMessage::Update {
+            data: data.to_vec(),
+        });
+        return;
+    }
+
+    let presence = presence.lock();
+    let peers = presence.peer_protocols.keys().copied().collect::<Vec<_>>();
+    offer_direct_sync(outbound_queue, &presence, own_id, peers.into_iter());
+}
+
+/// Sends neighbors every update no peer has acknowledged yet, such as edits made
+/// while we were disconnected.
+pub(crate) fn send_unacknowledged(
+    ctx: &SessionContext,
+    neighbors: impl Iterator<Item = EndpointId>,
+) -> Result<()> {
+    let acknowledged = ctx.acknowledged_version.lock().clone();
+    if acknowledged.includes_vv(&ctx.loro_doc.oplog_vv()) {
+        return Ok(());
+    }
+
+    let updates = ctx
+        .loro_doc
+        .export(loro::ExportMode::updates(&acknowledged))?;
+    if updates.len() > MAX_INLINE_UPDATE_SIZE {
+        // Too big for gossip, so have each neighbor pull it over the direct sync protocol
+        offer_direct_sync(
+            &ctx.outbound_queue,
+            &ctx.presence.lock(),
+            ctx.own_id,
+            neighbors.map(|neighbor| *neighbor.as_bytes()),
+        );
+    } else {
+        let _ = ctx
+            .outbound_queue
+            .send(GossipMessage::Update { data: updates });
+    }
+
+    Ok(())
+}
+
+pub(crate) fn handle_gossip_message(message: GossipMessage, ctx: &SessionContext) -> Result<()> {
+    match message {
+        GossipMessage::RequestData {
+            endpoint_id,
+            version,
+        } => {
+            if endpoint_id == ctx.own_id
+                || !is_elected_responder(
+                    ctx.presence.lock().awareness_cache.keys(),
+                    &ctx.own_id,
+                    &endpoint_id,
+                )
+            {
+                return Ok(());
+            }
+
+            let their_version = VersionVector::decode(&version)?;
+            if their_version.includes_vv(&ctx.loro_doc.oplog_vv()) {
+                return Ok(());
+            }
+
+            let updates = ctx
+                .loro_doc
+                .export(loro::ExportMode::updates(&their_version))?;
+            if updates.len() > MAX_INLINE_UPDATE_SIZE {
+                if !supports(&ctx.presence.lock(), &endpoint_id, Features::DIRECT_SYNC) {
+                    eprintln!("Requester cannot fetch large updates over direct sync");
+                    return Ok(());
+                }
+                let _ = ctx.outbound_queue.send(GossipMessage::DirectSyncOffer {
+                    requester_id: endpoint_id,
+                    responder_id: ctx.own_id,
+                });
+            } else {
+                let _ = ctx
+                    .outbound_queue
+                    .send(GossipMessage::Update { data: updates });
+            }
+        }
+        GossipMessage::Update { data } => {
+            ctx.loro_doc.import(&data)?;
+            ctx.emit(SessionEvent::DocumentChanged);
+        }
+        GossipMessage::Awareness(awareness) => {
+            if let Ok(their_version) = VersionVector::decode(&awareness.version) {
+                let own_version = ctx.loro_doc.oplog_vv();
+                let acknowledged = their_version.intersection(&own_version);
+                ctx.acknowledged_version.lock().merge(&acknowledged);
+                let last_request = *ctx.last_data_request.lock();
+                if should_request_again(&their_version, &own_version, last_request) {
+                    request_missing(ctx);
+                }
+            }
+            awareness::update_awareness_cache(&mut ctx.presence.lock(), ctx.own_id, *awareness);
+            ctx.emit(SessionEvent::PresenceChanged);
+        }
+        GossipMessage::DirectSyncOffer {
+            requester_id,
+            responder_id,
+        } => {
+            if requester_id != ctx.own_id {
+                return Ok(());
+            }
+
+            tokio::spawn(task_direct_sync(
+                ctx.clone(),
+                EndpointId::from_bytes(&responder_id)?,
+            ));
+        }
+        GossipMessage::Leave {
+            endpoint_id,
+            timestamp_ms,
+        } => {
+            let departed =
+                awareness::remove_departed(&mut ctx.presence.lock(), endpoint_id, timestamp_ms);
+            if departed {
+                ctx.emit(SessionEvent::PeerLeft);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(first: u8) -> IdBytes {
+        let mut id = [0; 32];
+        id[0] = first;
+        id
+    }
+
+    /// Whether `peer` answers `requester` when it knows everyone in `peers` but itself.
+    fn responds(peers: &[IdBytes], peer: &IdBytes, requester: &IdBytes) -> bool {
+        let known_peers = peers.iter().filter(|known| *known != peer);
+        is_elected_responder(known_peers, peer, requester)
+    }
+
+    #[test]
+    fn closest_peers_to_the_requester_respond() {
+        let requester = id(0);
+        let peers = [requester, id(1), id(2), id(3), id(4)];
+
+        let elected = peers[1..]
+            .iter()
+            .filter(|peer| responds(&peers, peer, &requester))
+            .copied()
+            .collect::<Vec<_>>();
+
+        assert_eq!(elected, [id(1), id(2)]);
+    }
+
+    #[test]
+    fn exactly_the_configured_number_responds() {
+        let peers = (0..20).map(|index| id(index * 13)).collect::<Vec<_>>();
+
+        for requester in &peers {
+            let responders = peers
+                .iter()
+                .filter(|peer| *peer != requester)
+                .filter(|peer| responds(&peers, peer, requester))
+                .count();
+            assert_eq!(responders, RESPONDERS_PER_REQUEST);
+        }
+    }
+
+    #[test]
+    fn peer_ahead_of_us_makes_us_ask_again() {
+        let ahead = LoroDoc::new();
+        ahead.get_text("text").insert(0, "hello").unwrap();
+        ahead.commit();
+        let behind = LoroDoc::new();
+        let asked_at = Instant::now();
+
+        assert!(should_request_again(
+            &ahead.oplog_vv(),
+            &behind.oplog_vv(),
+            None
+        ));
+        assert!(should_request_again(
+            &ahead.oplog_vv(),
+            &behind.oplog_vv(),
+            asked_at.checked_sub(REQUEST_RETRY_INTERVAL)
+        ));
+        // Not while the last request may still be answered
+        assert!(!should_request_again(
+            &ahead.oplog_vv(),
+            &behind.oplog_vv(),
+            Some(asked_at)
+        ));
+        // Nor when we already have everything the peer has
+        assert!(!should_request_again(
+            &behind.oplog_vv(),
+            &ahead.oplog_vv(),
+            None
+        ));
+    }
+
+    #[test]
+    fn everyone_responds_in_a_small_session() {
+        let requester = id(0);
+        let peers = [requester, id(200)];
+
+        assert!(responds(&peers, &id(200), &requester));
+    }
+}
@@ -0,0 +1,77 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
+use data_encoding::BASE32_NOPAD;
+use iroh::SecretKey;
+
+use crate::storage::{Access, write_atomic};
+
+const EXPORT_PREFIX: &str = "collabid";
+
+fn identity_path() -> Result<PathBuf> {
+    let dir = dirs::config_dir()
+        .context("No config directory available")?
+        .join("rusty-collab");
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir.join("identity.key"))
+}
+
+fn store(secret_key: &SecretKey) -> Result<()> {
+    // The key is all it takes to act as this user
+    write_atomic(&identity_path()?, &secret_key.to_bytes(), Access::Private)
+}
+
+/// The secret key this user's endpoint is bound with, created on first use so the
+/// endpoint id, and with it the user's color, stays the same across sessions.
+pub fn load_or_create() -> Result<SecretKey> {
+    let path = identity_path()?;
+    if path.exists() {
+        let Ok(bytes) = <[u8; 32]>::try_from(fs::read(&path)?) else {
+            bail!("Identity file {} is corrupt", path.display());
+        };
+        return Ok(SecretKey::from_bytes(&bytes));
+    }
+
+    let secret_key = SecretKey::from_bytes(&rand::random());
+    store(&secret_key)?;
+
+    Ok(secret_key)
+}
+
+/// Replaces the stored identity with a fresh one. Peers will see a new user.
+pub fn rotate() -> Result<SecretKey> {
+    let secret_key = SecretKey::from_bytes(&rand::random());
+    store(&secret_key)?;
+
+    Ok(secret_key)
+}
+
+/// Printable form of the stored identity, for moving it to another machine.
+pub fn export() -> Result<String> {
+    let secret_key = load_or_create()?;
+    let encoded = BASE32_NOPAD
+        .encode(&secret_key.to_bytes())
+        .to_ascii_lowercase();
+
+    Ok(format!("{EXPORT_PREFIX}{encoded}"))
+}
+
+/// Replaces the stored identity with one produced by [`export`].
+pub fn import(exported: &str) -> Result<SecretKey> {
+    let encoded = exported
+        .trim()
+        .strip_prefix(EXPORT_PREFIX)
+        .context("Exported identity must start with \"collabid\"")?;
+    let bytes = BASE32_NOPAD
+        .decode(encoded.to_ascii_uppercase().as_bytes())
+        .context("Exported identity is not valid base32")?;
+    let Ok(bytes) = <[u8; 32]>::try_from(bytes) else {
+        bail!("Exported identity has the wrong length");
+    };
+
+    let secret_key = SecretKey::from_bytes(&bytes);
+    store(&secret_key)?;
+
+    Ok(secret_key)
+}
@@ -0,0 +1,27 @@
+//! Networking and CRDT core of Rusty Collab: sessions over iroh gossip, Loro document
+//! sync, awareness and local storage. Has no UI dependency; the desktop app and the
+//! command-line peer are both consumers of [`Session`].
+
+pub mod authors;
+pub mod awareness;
+pub mod document_store;
+mod gossip_message;
+pub mod identity;
+mod message_errors;
+pub mod profile;
+pub mod protocol;
+pub mod rich_text;
+pub mod session;
+pub mod session_crypto;
+mod session_loop;
+pub mod session_ticket;
+mod setup;
+mod storage;
+mod sync_protocol;
+pub mod workspace;
+
+pub use message_errors::MessageErrors;
+pub use profile::Profile;
+pub use session::{Presence, Session, SessionEvent, SessionStart};
+pub use session_loop::ConnectionStatus;
+pub use setup::{DEFAULT_JOIN_TIMEOUT, SetupError, SetupOptions, SetupStage};
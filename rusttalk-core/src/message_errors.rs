@@ -0,0 +1,31 @@
+use std::collections::{HashMap, HashSet};
+
+use iroh::EndpointId;
+
+/// Tally of gossip messages that failed to decrypt, decode or import, per neighbor that
+/// delivered them. Gossip does not reveal a message's original author, so a neighbor
+/// relaying someone else's garbage is counted as well.
+#[derive(Default, Debug)]
+pub struct MessageErrors {
+    pub counts: HashMap<EndpointId, u32>,
+    /// Neighbors whose messages are dropped unread.
+    pub blocked: HashSet<EndpointId>,
+}
+
+impl MessageErrors {
+    /// Counts a failure from `peer`, blocking it once it reaches `blocklist_after`.
+    /// Returns whether this failure got the peer blocked.
+    pub(crate) fn record(&mut self, peer: EndpointId, blocklist_after: Option<u32>) -> bool {
+        let count = self.counts.entry(peer).or_default();
+        *count += 1;
+
+        match blocklist_after {
+            Some(limit) if *count >= limit => self.blocked.insert(peer),
+            _ => false,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.values().sum()
+    }
+}
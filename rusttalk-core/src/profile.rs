@@ -0,0 +1,189 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use postcard::{from_bytes, to_allocvec as to_bytes};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::awareness::IdBytes;
+use crate::storage::{Access, write_atomic};
+
+/// Hues closer than this are hard to tell apart on carets and chips.
+const MIN_HUE_DISTANCE: u16 = 30;
+const HUE_SEARCH_STEP: u16 = 15;
+
+/// How a user presents themselves to the other peers. Saved between runs and sent
+/// along with every awareness broadcast.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    /// Preferred hue in degrees. `None` derives one from the endpoint id.
+    pub hue: Option<u16>,
+    /// Shown on the avatar. Empty derives them from the name.
+    pub initials: String,
+}
+
+impl Profile {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// Up to two uppercase characters: the chosen initials, or the first letters of
+    /// the first two words of the name.
+    pub fn avatar_initials(&self) -> String {
+        let initials = if self.initials.trim().is_empty() {
+            self.name
+                .split_whitespace()
+                .filter_map(|word| word.chars().next())
+                .collect::<String>()
+        } else {
+            self.initials.trim().to_string()
+        };
+
+        match initials.chars().take(2).collect::<String>().to_uppercase() {
+            initials if initials.is_empty() => "?".to_string(),
+            initials => initials,
+        }
+    }
+}
+
+fn profile_path() -> Result<PathBuf> {
+    let dir = dirs::config_dir()
+        .context("No config directory available")?
+        .join("rusty-collab");
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir.join("profile.bin"))
+}
+
+/// The saved profile, or an empty one on first run.
+pub fn load() -> Profile {
+    profile_path()
+        .and_then(|path| Ok(fs::read(path)?))
+        .ok()
+        .and_then(|bytes| from_bytes(&bytes).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(profile: &Profile) -> Result<()> {
+    write_atomic(&profile_path()?, &to_bytes(profile)?, Access::Shared)
+}
+
+/// Hue for a peer without a preference, spread by a hash of its full endpoint id.
+pub fn default_hue(endpoint_id: &IdBytes) -> u16 {
+    let mut hash: u32 = 2166136261;
+    for byte in endpoint_id {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+
+    (hash % 360) as u16
+}
+
+fn hue_distance(a: u16, b: u16) -> u16 {
+    let distance = a.abs_diff(b) % 360;
+    distance.min(360 - distance)
+}
+
+/// Gives every participant a hue at least [`MIN_HUE_DISTANCE`] away from the others
+/// where possible. Peers who picked a hue go first, then everyone in endpoint id
+/// order, so every peer in the session arrives at the same assignment.
+pub fn assign_hues(
+    participants: impl IntoIterator<Item = (IdBytes, Option<u16>)>,
+) -> HashMap<IdBytes, u16> {
+    let mut participants = participants
+        .into_iter()
+        .map(|(endpoint_id, hue)| (hue.is_none(), endpoint_id, hue.map(|hue| hue % 360)))
+        .collect::<Vec<_>>();
+    participants.sort();
+
+    let mut assigned = HashMap::new();
+    for (_, endpoint_id, hue) in participants {
+        let preferred = hue.unwrap_or_else(|| default_hue(&endpoint_id));
+        let min_distance = |hue: u16| {
+            assigned
+                .values()
+                .map(|&other| hue_distance(hue, other))
+                .min()
+                .unwrap_or(u16::MAX)
+        };
+
+        // Walk away from the preferred hue in both directions until it is distinct
+        // enough, falling back to the most distinct one seen
+        let mut best = preferred;
+        for offset in (0..=180).step_by(HUE_SEARCH_STEP as usize) {
+            let candidates = [(preferred + offset) % 360, (preferred + 360 - offset) % 360];
+            if let Some(&hue) = candidates
+                .iter()
+                .find(|&&hue| min_distance(hue) >= MIN_HUE_DISTANCE)
+            {
+                best = hue;
+                break;
+            }
+            for hue in candidates {
+                if min_distance(hue) > min_distance(best) {
+                    best = hue;
+                }
+            }
+        }
+
+        assigned.insert(endpoint_id, best);
+    }
+
+    assigned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_distinct(hues: &HashMap<IdBytes, u16>) {
+        let hues = hues.values().copied().collect::<Vec<_>>();
+        for (index, &a) in hues.iter().enumerate() {
+            for &b in &hues[index + 1..] {
+                assert!(
+                    hue_distance(a, b) >= MIN_HUE_DISTANCE,
+                    "{a} and {b} are too close in {hues:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn assignment_does_not_depend_on_order() {
+        let participants = (0..8u8)
+            .map(|index| ([index; 32], (index % 3 == 0).then_some(120)))
+            .collect::<Vec<_>>();
+
+        let forward = assign_hues(participants.clone());
+        let backward = assign_hues(participants.into_iter().rev());
+
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn free_preferences_are_kept() {
+        let hues = assign_hues([([1; 32], Some(10)), ([2; 32], Some(200))]);
+
+        assert_eq!(hues[&[1; 32]], 10);
+        assert_eq!(hues[&[2; 32]], 200);
+    }
+
+    #[test]
+    fn clashing_preferences_are_spread_apart() {
+        // Twelve hues fit around the wheel at the minimum distance
+        let hues = assign_hues((0..12u8).map(|index| ([index; 32], Some(0))));
+
+        assert_eq!(hues.len(), 12);
+        assert_distinct(&hues);
+    }
+
+    #[test]
+    fn default_hues_are_spread_apart() {
+        let hues = assign_hues((0..8u8).map(|index| ([index; 32], None)));
+
+        assert_distinct(&hues);
+    }
+}
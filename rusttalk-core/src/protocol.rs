@@ -0,0 +1,359 @@
+use std::fmt;
+
+use anyhow::Result;
+use postcard::{from_bytes, take_from_bytes, to_allocvec as to_bytes};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{awareness::IdBytes, gossip_message::GossipMessage};
+
+/// Version of the gossip wire format spoken by this build. Bump it whenever a
+/// `GossipMessage` variant or a payload extension is added.
+pub const PROTOCOL_VERSION: u16 = 6;
+
+/// Oldest version this build can still exchange messages with. Awareness gained a
+/// profile in version 2, an activity in version 4, a viewport in version 5 and the
+/// open document in version 6, all as extensions that version 1 skips.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Optional capabilities a peer advertises in every envelope.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Features(u32);
+
+impl Features {
+    /// Serves and fetches large updates over the direct sync protocol.
+    pub const DIRECT_SYNC: Features = Features(1 << 0);
+    // Bit 1 was advertised for acknowledgements, which every version sends as its
+    // version in awareness anyway, so it stays unused
+
+    /// Everything this build supports.
+    pub const SUPPORTED: Features = Self::DIRECT_SYNC;
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// What a peer told us about the protocol it speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerProtocol {
+    pub version: u16,
+    pub min_version: u16,
+    pub features: Features,
+}
+
+impl PeerProtocol {
+    /// Whether both sides can read each other's messages.
+    pub fn is_compatible(&self) -> bool {
+        self.version >= MIN_PROTOCOL_VERSION && self.min_version <= PROTOCOL_VERSION
+    }
+}
+
+impl fmt::Display for PeerProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "protocol v{} (needs v{}+)",
+            self.version, self.min_version
+        )
+    }
+}
+
+/// Wraps every gossip payload. Its layout is frozen: later versions may only change
+/// what goes inside `payload`, so any build can still tell who sent a message and
+/// which protocol it speaks.
+///
+/// The payload is the message in its version 1 layout, followed by the fields added
+/// since as a list of separately encoded extensions. Decoders stop reading after the
+/// part they know, so new fields never break older builds.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    pub version: u16,
+    pub min_version: u16,
+    pub features: Features,
+    pub sender: IdBytes,
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn encode(sender: IdBytes, message: &GossipMessage) -> Result<Vec<u8>> {
+        Ok(to_bytes(&Envelope {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            features: Features::SUPPORTED,
+            sender,
+            payload: encode_payload(message)?,
+        })?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(from_bytes(bytes)?)
+    }
+
+    pub fn protocol(&self) -> PeerProtocol {
+        PeerProtocol {
+            version: self.version,
+            min_version: self.min_version,
+            features: self.features,
+        }
+    }
+
+    /// The wrapped message, or `None` when a newer build sent one we do not know,
+    /// which is safe to skip.
+    pub fn payload(&self) -> Result<Option<GossipMessage>> {
+        let (mut message, extensions) = match take_from_bytes::<GossipMessage>(&self.payload) {
+            Ok(decoded) => decoded,
+            Err(_) if self.version > PROTOCOL_VERSION => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if !extensions.is_empty() {
+            message.apply_extensions(&from_bytes::<Vec<Vec<u8>>>(extensions)?);
+        }
+
+        Ok(Some(message))
+    }
+}
+
+fn encode_payload(message: &GossipMessage) -> Result<Vec<u8>> {
+    let mut payload = to_bytes(message)?;
+    let extensions = message.extensions()?;
+    if !extensions.is_empty() {
+        payload.extend(to_bytes(&extensions)?);
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::awareness::{Activity, Awareness};
+    use crate::profile::Profile;
+
+    /// The gossip messages of protocol version 1, as the first release decoded them.
+    mod v1 {
+        use loro::{PeerID, cursor::Cursor};
+        use serde_derive::{Deserialize, Serialize};
+
+        use crate::awareness::IdBytes;
+
+        #[derive(Serialize, Deserialize)]
+        pub enum GossipMessage {
+            RequestData {
+                endpoint_id: IdBytes,
+                version: Vec<u8>,
+            },
+            Update {
+                data: Vec<u8>,
+            },
+            Awareness(Awareness),
+            DirectSyncOffer {
+                requester_id: IdBytes,
+                responder_id: IdBytes,
+            },
+        }
+
+        #[derive(Serialize, Deserialize)]
+        pub struct Awareness {
+            pub endpoint_id: IdBytes,
+            pub loro_peer_id: PeerID,
+            pub name: String,
+            pub loro_cursors: Option<(Cursor, Cursor)>,
+            pub version: Vec<u8>,
+            pub timestamp_ms: u64,
+        }
+
+        /// `Envelope::payload` as version 1 shipped it.
+        pub fn payload(envelope: &super::Envelope) -> anyhow::Result<Option<GossipMessage>> {
+            match postcard::from_bytes(&envelope.payload) {
+                Ok(message) => Ok(Some(message)),
+                Err(_) if envelope.version > 1 => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        }
+    }
+
+    fn awareness() -> Awareness {
+        Awareness {
+            endpoint_id: [1; 32],
+            loro_peer_id: 42,
+            profile: Profile {
+                name: "Ada".to_string(),
+                hue: Some(200),
+                initials: "AL".to_string(),
+            },
+            loro_cursors: None,
+            version: vec![1, 2, 3],
+            timestamp_ms: 1_000,
+            activity: Activity::Typing,
+            viewport: None,
+            document: "notes".to_string(),
+        }
+    }
+
+    fn roundtrip(message: &GossipMessage) -> Envelope {
+        Envelope::decode(&Envelope::encode([9; 32], message).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn every_version_since_the_first_is_compatible() {
+        for version in 1..=PROTOCOL_VERSION {
+            let protocol = PeerProtocol {
+                version,
+                min_version: 1,
+                features: Features::default(),
+            };
+            assert!(protocol.is_compatible(), "{protocol}");
+        }
+    }
+
+    #[test]
+    fn unknown_message_from_a_newer_version_is_skipped() {
+        // A variant index this build does not have
+        let payload = vec![200, 1, 2, 3];
+        let mut envelope = Envelope {
+            version: PROTOCOL_VERSION + 1,
+            min_version: MIN_PROTOCOL_VERSION,
+            features: Features::SUPPORTED,
+            sender: [1; 32],
+            payload,
+        };
+        assert!(envelope.payload().unwrap().is_none());
+
+        // From our own version it can only be garbage
+        envelope.version = PROTOCOL_VERSION;
+        assert!(envelope.payload().is_err());
+    }
+
+    #[test]
+    fn envelope_keeps_the_sender_and_protocol() {
+        let envelope = roundtrip(&GossipMessage::Update { data: vec![1, 2] });
+
+        assert_eq!(envelope.sender, [9; 32]);
+        assert_eq!(
+            envelope.protocol(),
+            PeerProtocol {
+                version: PROTOCOL_VERSION,
+                min_version: MIN_PROTOCOL_VERSION,
+                features: Features::SUPPORTED,
+            }
+        );
+        assert!(matches!(
+            envelope.payload().unwrap(),
+            Some(GossipMessage::Update { data }) if data == [1, 2]
+        ));
+    }
+
+    #[test]
+    fn too_new_a_peer_is_incompatible() {
+        let protocol = PeerProtocol {
+            version: PROTOCOL_VERSION + 2,
+            min_version: PROTOCOL_VERSION + 1,
+            features: Features::SUPPORTED,
+        };
+
+        assert!(!protocol.is_compatible());
+    }
+
+    #[test]
+    fn extensions_from_newer_versions_are_skipped() {
+        let mut payload = to_bytes(&GossipMessage::Update { data: vec![1, 2] }).unwrap();
+        payload.extend(to_bytes(&vec![to_bytes("added in a later version").unwrap()]).unwrap());
+        let envelope = Envelope {
+            version: PROTOCOL_VERSION + 1,
+            min_version: MIN_PROTOCOL_VERSION,
+            features: Features::SUPPORTED,
+            sender: [1; 32],
+            payload,
+        };
+
+        assert!(matches!(
+            envelope.payload().unwrap(),
+            Some(GossipMessage::Update { data }) if data == [1, 2]
+        ));
+    }
+
+    #[test]
+    fn version_one_reads_current_awareness() {
+        let envelope = roundtrip(&GossipMessage::Awareness(Box::new(awareness())));
+
+        let Some(v1::GossipMessage::Awareness(awareness)) = v1::payload(&envelope).unwrap() else {
+            panic!("expected awareness");
+        };
+        assert_eq!(awareness.endpoint_id, [1; 32]);
+        assert_eq!(awareness.loro_peer_id, 42);
+        assert_eq!(awareness.name, "Ada");
+        assert_eq!(awareness.version, vec![1, 2, 3]);
+        assert_eq!(awareness.timestamp_ms, 1_000);
+    }
+
+    #[test]
+    fn version_one_skips_unknown_messages() {
+        let envelope = roundtrip(&GossipMessage::Leave {
+            endpoint_id: [1; 32],
+            timestamp_ms: 1_000,
+        });
+
+        assert!(v1::payload(&envelope).unwrap().is_none());
+    }
+
+    #[test]
+    fn current_reads_version_one_awareness_with_defaults() {
+        let envelope = Envelope {
+            version: 1,
+            min_version: 1,
+            features: Features::default(),
+            sender: [1; 32],
+            payload: to_bytes(&v1::GossipMessage::Awareness(v1::Awareness {
+                endpoint_id: [1; 32],
+                loro_peer_id: 42,
+                name: "Ada".to_string(),
+                loro_cursors: None,
+                version: vec![1, 2, 3],
+                timestamp_ms: 1_000,
+            }))
+            .unwrap(),
+        };
+
+        let Some(GossipMessage::Awareness(awareness)) = envelope.payload().unwrap() else {
+            panic!("expected awareness");
+        };
+        assert_eq!(awareness.profile, Profile::new("Ada".to_string()));
+        assert_eq!(awareness.activity, Activity::Active);
+        assert!(awareness.viewport.is_none());
+        assert_eq!(awareness.document, crate::workspace::MAIN_DOCUMENT);
+    }
+
+    #[test]
+    fn current_roundtrips_every_extension() {
+        let envelope = roundtrip(&GossipMessage::Awareness(Box::new(awareness())));
+
+        let Some(GossipMessage::Awareness(decoded)) = envelope.payload().unwrap() else {
+            panic!("expected awareness");
+        };
+        assert_eq!(decoded.profile, awareness().profile);
+        assert_eq!(decoded.activity, Activity::Typing);
+        assert_eq!(decoded.document, "notes");
+    }
+
+    #[test]
+    fn current_ignores_extensions_from_newer_versions() {
+        let message = GossipMessage::Awareness(Box::new(awareness()));
+        let mut extensions = message.extensions().unwrap();
+        extensions.push(to_bytes("added in a later version").unwrap());
+        let mut payload = to_bytes(&message).unwrap();
+        payload.extend(to_bytes(&extensions).unwrap());
+        let envelope = Envelope {
+            version: PROTOCOL_VERSION + 1,
+            min_version: MIN_PROTOCOL_VERSION,
+            features: Features::SUPPORTED,
+            sender: [1; 32],
+            payload,
+        };
+
+        let Some(GossipMessage::Awareness(decoded)) = envelope.payload().unwrap() else {
+            panic!("expected awareness");
+        };
+        assert_eq!(decoded.profile, awareness().profile);
+        assert_eq!(decoded.document, "notes");
+    }
+}
@@ -0,0 +1,463 @@
+use std::ops::Range;
+
+use anyhow::Result;
+use loro::{ExpandType, LoroDoc, LoroText, LoroValue, StyleConfig, StyleConfigMap, TextDelta};
+
+const HEADING_KEY: &str = "heading";
+const LINK_KEY: &str = "link";
+
+/// Inline styles that are either on or off for a range of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextStyle {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Code,
+}
+
+impl TextStyle {
+    pub const ALL: [TextStyle; 5] = [
+        TextStyle::Bold,
+        TextStyle::Italic,
+        TextStyle::Underline,
+        TextStyle::Strikethrough,
+        TextStyle::Code,
+    ];
+
+    fn key(self) -> &'static str {
+        match self {
+            TextStyle::Bold => "bold",
+            TextStyle::Italic => "italic",
+            TextStyle::Underline => "underline",
+            TextStyle::Strikethrough => "strikethrough",
+            TextStyle::Code => "code",
+        }
+    }
+
+    /// Text typed right after a styled range continues the style, except for code,
+    /// which is usually closed off on purpose.
+    fn expand(self) -> ExpandType {
+        match self {
+            TextStyle::Code => ExpandType::None,
+            _ => ExpandType::After,
+        }
+    }
+}
+
+/// Everything marked on a piece of text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextAttributes {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub code: bool,
+    /// Heading level 1 to 3 of the line.
+    pub heading: Option<u8>,
+    pub link: Option<String>,
+}
+
+impl TextAttributes {
+    pub fn has(&self, style: TextStyle) -> bool {
+        match style {
+            TextStyle::Bold => self.bold,
+            TextStyle::Italic => self.italic,
+            TextStyle::Underline => self.underline,
+            TextStyle::Strikethrough => self.strikethrough,
+            TextStyle::Code => self.code,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &LoroValue) {
+        let enabled = matches!(value, LoroValue::Bool(true));
+        match key {
+            "bold" => self.bold = enabled,
+            "italic" => self.italic = enabled,
+            "underline" => self.underline = enabled,
+            "strikethrough" => self.strikethrough = enabled,
+            "code" => self.code = enabled,
+            HEADING_KEY => {
+                self.heading = match value {
+                    LoroValue::I64(level) => u8::try_from(*level).ok(),
+                    _ => None,
+                }
+            }
+            LINK_KEY => {
+                self.link = match value {
+                    LoroValue::String(url) => Some(url.to_string()),
+                    _ => None,
+                }
+            }
+            // Styles from newer builds are kept in the document, just not shown
+            _ => {}
+        }
+    }
+}
+
+/// A run of text with the same attributes, in Unicode scalar positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyledSpan {
+    pub range: Range<usize>,
+    pub attributes: TextAttributes,
+}
+
+/// Tells the document how each mark behaves at its edges. Every peer has to use the
+/// same configuration, so it is applied to each document as it is created.
+pub(crate) fn configure_text_styles(loro_doc: &LoroDoc) {
+    let mut styles = StyleConfigMap::new();
+    for style in TextStyle::ALL {
+        styles.insert(
+            style.key().into(),
+            StyleConfig {
+                expand: style.expand(),
+            },
+        );
+    }
+    // Text typed at the end of a heading belongs to it up to a line break (see
+    // `end_heading_at_line_break`), while links cover exact ranges and never grow
+    styles.insert(
+        HEADING_KEY.into(),
+        StyleConfig {
+            expand: ExpandType::After,
+        },
+    );
+    styles.insert(
+        LINK_KEY.into(),
+        StyleConfig {
+            expand: ExpandType::None,
+        },
+    );
+    loro_doc.config_text_style(styles);
+}
+
+/// The text split into runs of equal attributes, covering it from start to end.
+pub fn styled_spans(text: &LoroText) -> Vec<StyledSpan> {
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    for delta in text.to_delta() {
+        let TextDelta::Insert { insert, attributes } = delta else {
+            continue;
+        };
+
+        let mut span_attributes = TextAttributes::default();
+        for (key, value) in attributes.iter().flatten() {
+            span_attributes.set(key, value);
+        }
+
+        let len = insert.chars().count();
+        spans.push(StyledSpan {
+            range: pos..pos + len,
+            attributes: span_attributes,
+        });
+        pos += len;
+    }
+
+    spans
+}
+
+/// Whether every character in `range` satisfies `predicate`.
+fn all_in_range(
+    spans: &[StyledSpan],
+    range: &Range<usize>,
+    predicate: impl Fn(&TextAttributes) -> bool,
+) -> bool {
+    spans
+        .iter()
+        .filter(|span| span.range.start < range.end && range.start < span.range.end)
+        .all(|span| predicate(&span.attributes))
+}
+
+/// Turns `style` off if all of `range` has it, and on for all of it otherwise.
+/// Marks from different peers on overlapping ranges merge in the CRDT.
+pub(crate) fn toggle_style(text: &LoroText, range: Range<usize>, style: TextStyle) -> Result<()> {
+    if range.is_empty() {
+        return Ok(());
+    }
+
+    if all_in_range(&styled_spans(text), &range, |attributes| {
+        attributes.has(style)
+    }) {
+        text.unmark(range, style.key())?;
+    } else {
+        text.mark(range, style.key(), true)?;
+    }
+
+    Ok(())
+}
+
+/// Toggles heading `level` on every line the range touches.
+pub(crate) fn toggle_heading(text: &LoroText, range: Range<usize>, level: u8) -> Result<()> {
+    let content = text.to_string();
+    let chars = content.chars().collect::<Vec<_>>();
+    let (range_start, range_end) = (range.start.min(chars.len()), range.end.min(chars.len()));
+    let start = chars[..range_start]
+        .iter()
+        .rposition(|c| *c == '\n')
+        .map_or(0, |newline| newline + 1);
+    let end = chars[range_end..]
+        .iter()
+        .position(|c| *c == '\n')
+        .map_or(chars.len(), |newline| range_end + newline);
+    let lines = start..end;
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    if all_in_range(&styled_spans(text), &lines, |attributes| {
+        attributes.heading == Some(level)
+    }) {
+        text.unmark(lines, HEADING_KEY)?;
+    } else {
+        text.mark(lines, HEADING_KEY, level as i64)?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the whole text with `new`, recording the minimal edit.
+pub(crate) fn update_text(text: &LoroText, new: &str) -> Result<()> {
+    let old = text.to_string();
+    text.update(new, Default::default())?;
+
+    let (old_len, new_len) = (old.chars().count(), new.chars().count());
+    let prefix = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old
+        .chars()
+        .rev()
+        .zip(new.chars().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(old_len.min(new_len) - prefix);
+    end_heading_at_line_break(text, prefix..new_len - suffix)
+}
+
+/// Adds `insert` at the end of the text.
+pub(crate) fn append_text(text: &LoroText, insert: &str) -> Result<()> {
+    let start = text.len_unicode();
+    text.insert(start, insert)?;
+    end_heading_at_line_break(text, start..start + insert.chars().count())
+}
+
+/// Headings grow with text typed at their end, so a line break typed or pasted
+/// into one would carry the heading on to the next line. It stops at the break
+/// instead.
+fn end_heading_at_line_break(text: &LoroText, inserted: Range<usize>) -> Result<()> {
+    let Some(newline) = text
+        .slice(inserted.start, inserted.end)?
+        .chars()
+        .position(|c| c == '\n')
+    else {
+        return Ok(());
+    };
+
+    let broken = inserted.start + newline..inserted.end;
+    if !all_in_range(&styled_spans(text), &broken, |attributes| {
+        attributes.heading.is_none()
+    }) {
+        text.unmark(broken, HEADING_KEY)?;
+    }
+
+    Ok(())
+}
+
+/// Links the range to `url`, or removes links from it when `url` is `None`.
+pub(crate) fn set_link(text: &LoroText, range: Range<usize>, url: Option<&str>) -> Result<()> {
+    if range.is_empty() {
+        return Ok(());
+    }
+
+    match url {
+        Some(url) => text.mark(range, LINK_KEY, url)?,
+        None => text.unmark(range, LINK_KEY)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use loro::ExportMode;
+
+    use super::*;
+
+    fn text_with(content: &str) -> (LoroDoc, LoroText) {
+        let loro_doc = LoroDoc::new();
+        configure_text_styles(&loro_doc);
+        let text = loro_doc.get_text("text");
+        text.insert(0, content).unwrap();
+        (loro_doc, text)
+    }
+
+    /// Whether each character satisfies `has`, as a string of `x` and `.`.
+    fn styled(text: &LoroText, has: impl Fn(&TextAttributes) -> bool) -> String {
+        styled_spans(text)
+            .iter()
+            .flat_map(|span| {
+                let c = if has(&span.attributes) { 'x' } else { '.' };
+                std::iter::repeat_n(c, span.range.len())
+            })
+            .collect()
+    }
+
+    fn bold(text: &LoroText) -> String {
+        styled(text, |attributes| attributes.bold)
+    }
+
+    #[test]
+    fn toggle_style_turns_a_plain_range_on_and_off() {
+        let (_doc, text) = text_with("hello world");
+
+        toggle_style(&text, 0..5, TextStyle::Bold).unwrap();
+        assert_eq!(bold(&text), "xxxxx......");
+
+        toggle_style(&text, 0..5, TextStyle::Bold).unwrap();
+        assert_eq!(bold(&text), "...........");
+    }
+
+    #[test]
+    fn toggle_style_on_a_mixed_selection_styles_all_of_it() {
+        let (_doc, text) = text_with("hello world");
+        toggle_style(&text, 0..5, TextStyle::Bold).unwrap();
+
+        toggle_style(&text, 3..8, TextStyle::Bold).unwrap();
+        assert_eq!(bold(&text), "xxxxxxxx...");
+
+        toggle_style(&text, 3..8, TextStyle::Bold).unwrap();
+        assert_eq!(bold(&text), "xxx........");
+    }
+
+    #[test]
+    fn toggle_style_leaves_other_styles_alone() {
+        let (_doc, text) = text_with("hello");
+        toggle_style(&text, 0..5, TextStyle::Italic).unwrap();
+
+        toggle_style(&text, 1..3, TextStyle::Bold).unwrap();
+        assert_eq!(bold(&text), ".xx..");
+        assert_eq!(styled(&text, |attributes| attributes.italic), "xxxxx");
+    }
+
+    #[test]
+    fn toggle_style_ignores_an_empty_range() {
+        let (_doc, text) = text_with("hello");
+
+        toggle_style(&text, 2..2, TextStyle::Bold).unwrap();
+        assert_eq!(bold(&text), ".....");
+    }
+
+    #[test]
+    fn heading_covers_the_whole_line_but_not_the_newline() {
+        let (_doc, text) = text_with("one\ntwo words\nthree");
+
+        toggle_heading(&text, 6..7, 2).unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading == Some(2)),
+            "....xxxxxxxxx......"
+        );
+
+        toggle_heading(&text, 4..4, 2).unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading.is_some()),
+            "..................."
+        );
+    }
+
+    #[test]
+    fn heading_over_several_lines_marks_each_of_them() {
+        let (_doc, text) = text_with("one\ntwo\nthree");
+
+        toggle_heading(&text, 2..5, 1).unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading == Some(1)),
+            "xxxxxxx......"
+        );
+    }
+
+    #[test]
+    fn heading_on_a_mixed_selection_switches_it_to_the_level() {
+        let (_doc, text) = text_with("one\ntwo");
+        toggle_heading(&text, 0..0, 1).unwrap();
+
+        toggle_heading(&text, 0..5, 2).unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading == Some(2)),
+            "xxxxxxx"
+        );
+
+        toggle_heading(&text, 0..5, 2).unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading.is_some()),
+            "......."
+        );
+    }
+
+    #[test]
+    fn heading_grows_with_typing_but_stops_at_a_line_break() {
+        let (_doc, text) = text_with("Title");
+        toggle_heading(&text, 0..0, 1).unwrap();
+
+        update_text(&text, "Titles").unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading == Some(1)),
+            "xxxxxx"
+        );
+
+        update_text(&text, "Titles\nbody text").unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading.is_some()),
+            "xxxxxx.........."
+        );
+
+        append_text(&text, "\nmore").unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading.is_some()),
+            "xxxxxx..............."
+        );
+    }
+
+    #[test]
+    fn line_break_inside_a_heading_splits_it_into_two() {
+        let (_doc, text) = text_with("one two");
+        toggle_heading(&text, 0..0, 2).unwrap();
+
+        update_text(&text, "one\ntwo").unwrap();
+        assert_eq!(
+            styled(&text, |attributes| attributes.heading == Some(2)),
+            "xxx.xxx"
+        );
+    }
+
+    #[test]
+    fn overlapping_styles_from_two_peers_converge() {
+        let (doc_a, text_a) = text_with("hello world");
+        let doc_b = LoroDoc::new();
+        configure_text_styles(&doc_b);
+        doc_b
+            .import(&doc_a.export(ExportMode::all_updates()).unwrap())
+            .unwrap();
+        let text_b = doc_b.get_text("text");
+
+        toggle_style(&text_a, 0..7, TextStyle::Bold).unwrap();
+        toggle_style(&text_b, 4..11, TextStyle::Bold).unwrap();
+        toggle_style(&text_b, 2..9, TextStyle::Italic).unwrap();
+        doc_a.commit();
+        doc_b.commit();
+        doc_a
+            .import(&doc_b.export(ExportMode::all_updates()).unwrap())
+            .unwrap();
+        doc_b
+            .import(&doc_a.export(ExportMode::all_updates()).unwrap())
+            .unwrap();
+
+        assert_eq!(styled_spans(&text_a), styled_spans(&text_b));
+        assert_eq!(bold(&text_a), "xxxxxxxxxxx");
+        assert_eq!(
+            styled(&text_a, |attributes| attributes.italic),
+            "..xxxxxxx.."
+        );
+    }
+}
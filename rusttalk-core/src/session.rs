@@ -0,0 +1,651 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use iroh::{
+    Endpoint, EndpointAddr, EndpointId, SecretKey, address_lookup::MemoryLookup,
+    endpoint::BindError, protocol::Router,
+};
+use iroh_gossip::{Gossip, TopicId};
+use loro::{Frontiers, LoroDoc, VersionVector, cursor::Cursor};
+use parking_lot::{Mutex, MutexGuard};
+use tokio::{
+    select,
+    sync::{
+        broadcast,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::timeout,
+};
+
+use crate::{
+    authors,
+    awareness::{
+        AwarenessCache, DepartedPeer, IdBytes, LocalActivity, LoroCursors, broadcast_awareness,
+    },
+    document_store::{self, RecentDocument},
+    gossip_message::{GossipMessage, send_local_update},
+    message_errors::MessageErrors,
+    profile::{self, Profile},
+    protocol::PeerProtocol,
+    rich_text::{self, TextStyle},
+    session_crypto::{SessionCipher, SessionSecret},
+    session_loop::{AUTOSAVE_PERIOD, ConnectionStatus, task_main_loop},
+    session_ticket::SessionTicket,
+    setup::{SetupError, SetupOptions, SetupStage, cancelled},
+    sync_protocol::{self, SyncProtocol},
+    workspace::{self, DocumentFormat, DocumentId, DocumentInfo, MAIN_DOCUMENT},
+};
+
+#[derive(Clone)]
+pub enum SessionStart {
+    Create,
+    Join {
+        ticket: String,
+    },
+    /// Like `Join`, but without waiting for a neighbor, so a seed peer can come up
+    /// before anyone else is online.
+    Serve {
+        ticket: String,
+    },
+    Reopen(RecentDocument),
+}
+
+/// Notifications for consumers of a running session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// Remote updates were imported into the document.
+    DocumentChanged,
+    /// Other peers' awareness changed or expired.
+    PresenceChanged,
+    /// The [`ConnectionStatus`] changed.
+    StatusChanged,
+    /// A gossip message failed to decrypt, decode or import; see [`MessageErrors`].
+    MessageRejected,
+    /// A peer announced it is leaving; see [`Presence::recently_left`].
+    PeerLeft,
+}
+
+/// What the session knows about the people in it.
+pub struct Presence {
+    pub awareness_cache: AwarenessCache,
+    /// Protocol each peer announced in its latest envelope and when that arrived,
+    /// including peers whose messages we cannot read. Expires with awareness.
+    pub peer_protocols: HashMap<IdBytes, (PeerProtocol, Instant)>,
+    /// Our own selection, shared with peers in awareness broadcasts.
+    pub own_cursors: LoroCursors,
+    /// Top of our own viewport, shared the same way.
+    pub own_viewport: Option<Cursor>,
+    /// Workspace document we have open, shared the same way.
+    pub own_document: DocumentId,
+    /// What the local user is doing, shared with peers as an
+    /// [`Activity`](crate::awareness::Activity).
+    pub own_activity: LocalActivity,
+    /// Peers that left within the last few seconds, oldest first.
+    pub recently_left: Vec<DepartedPeer>,
+}
+
+pub(crate) type OutboundQueue = UnboundedSender<GossipMessage>;
+
+/// Everything the network loop, the sync protocol and the [`Session`] handle share.
+#[derive(Clone)]
+pub(crate) struct SessionContext {
+    pub own_id: IdBytes,
+    pub own_profile: Profile,
+    pub topic_id: TopicId,
+    pub secret: SessionSecret,
+    pub cipher: SessionCipher,
+    pub loro_doc: LoroDoc,
+    pub iroh_endpoint: Endpoint,
+    pub presence: Arc<Mutex<Presence>>,
+    pub saved_version: Arc<Mutex<VersionVector>>,
+    /// The part of our document that peers have confirmed receiving.
+    pub acknowledged_version: Arc<Mutex<VersionVector>>,
+    /// When we last asked peers for updates we are missing.
+    pub last_data_request: Arc<Mutex<Option<Instant>>>,
+    pub message_errors: Arc<Mutex<MessageErrors>>,
+    pub blocklist_after: Option<u32>,
+    pub status: Arc<Mutex<ConnectionStatus>>,
+    pub outbound_queue: OutboundQueue,
+    pub events: broadcast::Sender<SessionEvent>,
+}
+
+impl SessionContext {
+    /// Context for a session on `ticket`'s topic that nobody has been heard from in
+    /// yet. Messages sent through it queue up for the main loop to broadcast.
+    pub(crate) fn new(
+        own_profile: Profile,
+        ticket: &SessionTicket,
+        cipher: SessionCipher,
+        loro_doc: LoroDoc,
+        iroh_endpoint: Endpoint,
+        blocklist_after: Option<u32>,
+    ) -> (SessionContext, UnboundedReceiver<GossipMessage>) {
+        const EVENT_CAPACITY: usize = 64;
+
+        let (outbound_queue, outbound_queue_rx) = mpsc::unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let ctx = SessionContext {
+            own_id: iroh_endpoint.id().as_bytes().to_owned(),
+            own_profile,
+            topic_id: ticket.topic_id,
+            secret: ticket.secret,
+            cipher,
+            saved_version: Arc::new(Mutex::new(loro_doc.oplog_vv())),
+            acknowledged_version: Arc::new(Mutex::new(VersionVector::new())),
+            last_data_request: Arc::new(Mutex::new(None)),
+            message_errors: Arc::new(Mutex::new(MessageErrors::default())),
+            blocklist_after,
+            loro_doc,
+            iroh_endpoint,
+            presence: Arc::new(Mutex::new(Presence {
+                awareness_cache: Default::default(),
+                peer_protocols: Default::default(),
+                own_cursors: None,
+                own_viewport: None,
+                own_document: MAIN_DOCUMENT.to_string(),
+                own_activity: Default::default(),
+                recently_left: Vec::new(),
+            })),
+            status: Arc::new(Mutex::new(ConnectionStatus::default())),
+            outbound_queue,
+            events,
+        };
+
+        (ctx, outbound_queue_rx)
+    }
+
+    /// Sends every edit made to the document locally to the session. Captures only
+    /// what it needs, since the document keeps the closure alive.
+    pub(crate) fn subscribe_local_updates(&self) -> loro::Subscription {
+        let outbound_queue = self.outbound_queue.clone();
+        let presence = self.presence.clone();
+        let own_id = self.own_id;
+        self.loro_doc.subscribe_local_update(Box::new(move |bytes| {
+            send_local_update(&outbound_queue, &presence, own_id, bytes);
+            true
+        }))
+    }
+
+    /// Ticket that others paste into the lobby to join this session. It carries our
+    /// own full address plus every peer we currently know about, so joining still
+    /// works after we leave.
+    pub fn ticket(&self) -> SessionTicket {
+        let mut peers = vec![self.iroh_endpoint.addr()];
+        peers.extend(
+            self.presence
+                .lock()
+                .awareness_cache
+                .keys()
+                .filter_map(|id_bytes| EndpointId::from_bytes(id_bytes).ok())
+                .map(EndpointAddr::new),
+        );
+
+        SessionTicket {
+            topic_id: self.topic_id,
+            secret: self.secret,
+            peers,
+        }
+    }
+
+    pub fn emit(&self, event: SessionEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+}
+
+/// A running collaborative session: one document synced with every peer on its topic.
+pub struct Session {
+    ctx: SessionContext,
+    iroh_gossip: Gossip,
+    iroh_router: Router,
+    _loro_sub: loro::Subscription,
+    main_loop_handle: JoinHandle<()>,
+    leave_tx: oneshot::Sender<()>,
+}
+
+impl Session {
+    pub async fn start(profile: Profile, start: SessionStart) -> Result<Session, SetupError> {
+        setup(profile, start, SetupOptions::default()).await
+    }
+
+    pub async fn start_with(
+        profile: Profile,
+        start: SessionStart,
+        options: SetupOptions,
+    ) -> Result<Session, SetupError> {
+        setup(profile, start, options).await
+    }
+
+    pub fn own_id(&self) -> IdBytes {
+        self.ctx.own_id
+    }
+
+    pub fn own_name(&self) -> &str {
+        &self.ctx.own_profile.name
+    }
+
+    pub fn own_profile(&self) -> &Profile {
+        &self.ctx.own_profile
+    }
+
+    /// Distinct hue for ourselves and every peer in the awareness cache, see
+    /// [`profile::assign_hues`].
+    pub fn peer_hues(&self) -> HashMap<IdBytes, u16> {
+        let presence = self.ctx.presence.lock();
+        let peers = presence
+            .awareness_cache
+            .values()
+            .map(|(awareness, _)| (awareness.endpoint_id, awareness.profile.hue));
+
+        profile::assign_hues(
+            std::iter::once((self.ctx.own_id, self.ctx.own_profile.hue)).chain(peers),
+        )
+    }
+
+    pub fn topic_id(&self) -> TopicId {
+        self.ctx.topic_id
+    }
+
+    pub fn loro_doc(&self) -> &LoroDoc {
+        &self.ctx.loro_doc
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.ctx.iroh_endpoint
+    }
+
+    pub fn ticket(&self) -> SessionTicket {
+        self.ctx.ticket()
+    }
+
+    pub fn presence(&self) -> MutexGuard<'_, Presence> {
+        self.ctx.presence.lock()
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        *self.ctx.status.lock()
+    }
+
+    pub fn message_errors(&self) -> MutexGuard<'_, MessageErrors> {
+        self.ctx.message_errors.lock()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.ctx.events.subscribe()
+    }
+
+    /// Every document in the session's workspace.
+    pub fn documents(&self) -> Vec<DocumentInfo> {
+        workspace::documents(&self.ctx.loro_doc)
+    }
+
+    pub fn create_document(&self, title: &str) -> Result<DocumentId> {
+        workspace::create(&self.ctx.loro_doc, title)
+    }
+
+    pub fn rename_document(&self, id: &str, title: &str) -> Result<()> {
+        workspace::rename(&self.ctx.loro_doc, id, title)
+    }
+
+    pub fn delete_document(&self, id: &str) -> Result<()> {
+        workspace::delete(&self.ctx.loro_doc, id)
+    }
+
+    pub fn set_document_format(&self, id: &str, format: DocumentFormat) -> Result<()> {
+        workspace::set_format(&self.ctx.loro_doc, id, format)
+    }
+
+    /// Restores one document to how it was at `frontiers`; see [`workspace::restore`].
+    pub fn restore_document(&self, id: &str, frontiers: &Frontiers) -> Result<()> {
+        workspace::restore(&self.ctx.loro_doc, id, frontiers)?;
+        self.update_activity(|activity| activity.record_input(true))
+    }
+
+    /// Switches the document we show peers as open. Our cursors and viewport belong
+    /// to the previous one, so they are cleared.
+    pub fn open_document(&self, id: &str) -> Result<()> {
+        {
+            let mut presence = self.ctx.presence.lock();
+            presence.own_document = id.to_string();
+            presence.own_cursors = None;
+            presence.own_viewport = None;
+        }
+        broadcast_awareness(&self.ctx)
+    }
+
+    /// Replaces a document's text, recording the minimal edit as a local change.
+    pub fn set_text(&self, document: &str, text: &str) -> Result<()> {
+        rich_text::update_text(
+            &workspace::document_text(&self.ctx.loro_doc, document),
+            text,
+        )?;
+        self.ctx.loro_doc.commit();
+        self.update_activity(|activity| activity.record_input(true))?;
+
+        Ok(())
+    }
+
+    pub fn append_text(&self, document: &str, text: &str) -> Result<()> {
+        rich_text::append_text(
+            &workspace::document_text(&self.ctx.loro_doc, document),
+            text,
+        )?;
+        self.ctx.loro_doc.commit();
+        self.update_activity(|activity| activity.record_input(true))?;
+
+        Ok(())
+    }
+
+    /// Toggles an inline style on a range of characters of a document.
+    pub fn toggle_style(
+        &self,
+        document: &str,
+        range: Range<usize>,
+        style: TextStyle,
+    ) -> Result<()> {
+        let text = workspace::document_text(&self.ctx.loro_doc, document);
+        rich_text::toggle_style(&text, range, style)?;
+        self.ctx.loro_doc.commit();
+
+        Ok(())
+    }
+
+    /// Toggles a heading level on every line the range touches.
+    pub fn toggle_heading(&self, document: &str, range: Range<usize>, level: u8) -> Result<()> {
+        let text = workspace::document_text(&self.ctx.loro_doc, document);
+        rich_text::toggle_heading(&text, range, level)?;
+        self.ctx.loro_doc.commit();
+
+        Ok(())
+    }
+
+    /// Links a range to `url`, or unlinks it when `url` is `None`.
+    pub fn set_link(&self, document: &str, range: Range<usize>, url: Option<&str>) -> Result<()> {
+        let text = workspace::document_text(&self.ctx.loro_doc, document);
+        rich_text::set_link(&text, range, url)?;
+        self.ctx.loro_doc.commit();
+
+        Ok(())
+    }
+
+    /// Notes input that did not edit the document, such as pointer movement.
+    pub fn record_input(&self) -> Result<()> {
+        self.update_activity(|activity| activity.record_input(false))
+    }
+
+    /// Whether the user is looking at this session; peers see us as away otherwise.
+    pub fn set_focused(&self, focused: bool) -> Result<()> {
+        self.update_activity(|activity| activity.focused = focused)
+    }
+
+    /// Applies a change to our local activity and tells peers right away when the
+    /// [`Activity`](crate::awareness::Activity) they see changes. Going idle is
+    /// picked up by the regular awareness refresh instead.
+    fn update_activity(&self, update: impl FnOnce(&mut LocalActivity)) -> Result<()> {
+        let changed = {
+            let mut presence = self.ctx.presence.lock();
+            let before = presence.own_activity.activity();
+            update(&mut presence.own_activity);
+            presence.own_activity.activity() != before
+        };
+
+        if changed {
+            broadcast_awareness(&self.ctx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates our selection and tells peers about it right away.
+    pub fn set_cursors(&self, cursors: LoroCursors) -> Result<()> {
+        self.ctx.presence.lock().own_cursors = cursors;
+        broadcast_awareness(&self.ctx)
+    }
+
+    /// Updates the top of our viewport. Scrolling changes it every frame, so peers
+    /// only hear about it with the next periodic awareness refresh.
+    pub fn set_viewport(&self, viewport: Option<Cursor>) {
+        self.ctx.presence.lock().own_viewport = viewport;
+    }
+
+    /// Saves the document, tells peers we are leaving and shuts the session down.
+    pub async fn leave(self) {
+        let _ = document_store::save_document(&self.ctx);
+        self.shut_down().await;
+    }
+
+    /// Tells peers we are leaving and shuts the session down without saving.
+    async fn shut_down(mut self) {
+        const LEAVE_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+        // Let the main loop flush queued messages and the leave announcement first
+        if self.leave_tx.send(()).is_ok() {
+            let _ = timeout(LEAVE_FLUSH_TIMEOUT, &mut self.main_loop_handle).await;
+        }
+
+        let _ = self.iroh_gossip.shutdown().await;
+        self.main_loop_handle.abort();
+        let _ = self.iroh_router.shutdown().await;
+        self.ctx.iroh_endpoint.close().await;
+    }
+}
+
+async fn bind_endpoint(secret_key: Option<SecretKey>) -> Result<Endpoint, BindError> {
+    match secret_key {
+        Some(secret_key) => Endpoint::builder().secret_key(secret_key).bind().await,
+        None => Endpoint::bind().await,
+    }
+}
+
+async fn setup(
+    profile: Profile,
+    start: SessionStart,
+    mut options: SetupOptions,
+) -> Result<Session, SetupError> {
+    const GOSSIP_MAX_MESSAGE_SIZE: usize = 2 * 1024 * 1024;
+    /// A slow sync goes on in the background after this, with the document shown as
+    /// far as it got.
+    const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+    // Join an existing session from its ticket, reopen a stored one with its last known
+    // peers, or mint a fresh topic and secret for a new one
+    let (ticket, is_joining) = match start {
+        SessionStart::Create => (
+            SessionTicket {
+                topic_id: TopicId::from_bytes(rand::random()),
+                secret: rand::random(),
+                peers: vec![],
+            },
+            false,
+        ),
+        SessionStart::Join { ticket } => (
+            ticket
+                .parse::<SessionTicket>()
+                .map_err(SetupError::InvalidTicket)?,
+            true,
+        ),
+        SessionStart::Serve { ticket } => (
+            ticket
+                .parse::<SessionTicket>()
+                .map_err(SetupError::InvalidTicket)?,
+            false,
+        ),
+        SessionStart::Reopen(document) => (
+            SessionTicket {
+                topic_id: document.topic_id,
+                secret: document.secret,
+                peers: document.peers,
+            },
+            false,
+        ),
+    };
+
+    // Load the stored copy first, so a storage failure never leaves network state behind
+    let loro_doc = LoroDoc::new();
+    // Timestamps and per-minute change grouping feed the history panel
+    loro_doc.set_record_timestamp(true);
+    loro_doc.set_change_merge_interval(60);
+    rich_text::configure_text_styles(&loro_doc);
+    let stored_snapshot =
+        document_store::load_document(&ticket.topic_id).map_err(SetupError::Storage)?;
+    if let Some(snapshot) = stored_snapshot {
+        loro_doc
+            .import(&snapshot)
+            .map_err(|err| SetupError::Storage(err.into()))?;
+    }
+
+    options.report(SetupStage::Binding);
+    let mut cancel = options.cancel.take();
+    let iroh_endpoint = select! {
+        result = bind_endpoint(options.secret_key.clone()) => result.map_err(|err| SetupError::Bind(err.into()))?,
+        _ = cancelled(&mut cancel) => return Err(SetupError::Cancelled),
+    };
+    let iroh_gossip = Gossip::builder()
+        .max_message_size(GOSSIP_MAX_MESSAGE_SIZE)
+        .spawn(iroh_endpoint.clone());
+
+    // Accept connections before dialing anyone, so peers that find us while we join
+    // can already reach gossip and the sync protocol
+    let cipher = SessionCipher::new(&ticket.secret);
+
+    let iroh_router = Router::builder(iroh_endpoint.clone())
+        .accept(iroh_gossip::ALPN, iroh_gossip.clone())
+        .accept(
+            sync_protocol::ALPN,
+            SyncProtocol {
+                topic_id: ticket.topic_id,
+                cipher: cipher.clone(),
+                loro_doc: loro_doc.clone(),
+            },
+        )
+        .spawn();
+
+    let bootstrap_nodes: Vec<EndpointId> = ticket.peers.iter().map(|addr| addr.id).collect();
+    let join = async {
+        options.report(SetupStage::Dialing);
+        // Make the ticket's relay and direct addresses known to the endpoint before dialing
+        iroh_endpoint
+            .address_lookup()
+            .add(MemoryLookup::from_endpoint_info(ticket.peers.clone()));
+
+        let mut gossip_topic = iroh_gossip
+            .subscribe(ticket.topic_id, bootstrap_nodes.clone())
+            .await
+            .map_err(|err| SetupError::Subscribe(err.into()))?;
+
+        if is_joining {
+            options.report(SetupStage::Subscribing);
+            timeout(options.join_timeout, gossip_topic.joined())
+                .await
+                .map_err(|_| SetupError::JoinTimeout(options.join_timeout))?
+                .map_err(|err| SetupError::Subscribe(err.into()))?;
+        }
+
+        Ok(gossip_topic)
+    };
+    let join_result = select! {
+        result = join => result,
+        _ = cancelled(&mut cancel) => Err(SetupError::Cancelled),
+    };
+    let gossip_topic = match join_result {
+        Ok(gossip_topic) => gossip_topic,
+        Err(err) => {
+            let _ = iroh_gossip.shutdown().await;
+            let _ = iroh_router.shutdown().await;
+            iroh_endpoint.close().await;
+            return Err(err);
+        }
+    };
+
+    let (ctx, outbound_queue_rx) = SessionContext::new(
+        profile,
+        &ticket,
+        cipher,
+        loro_doc,
+        iroh_endpoint,
+        options.blocklist_after,
+    );
+    let loro_sub = ctx.subscribe_local_updates();
+
+    // Sent once the main loop runs, like any other local edit
+    if let Err(err) = authors::record(&ctx.loro_doc, ctx.own_id, &ctx.own_profile) {
+        eprintln!("Could not record ourselves as an author: {err:#}");
+    }
+
+    let mut events = ctx.events.subscribe();
+    let (leave_tx, leave_rx) = oneshot::channel();
+    // A join can be cancelled until it has synced, and then nothing of it is saved
+    let first_autosave = if is_joining {
+        SYNC_TIMEOUT + AUTOSAVE_PERIOD
+    } else {
+        AUTOSAVE_PERIOD
+    };
+    let main_loop_handle = tokio::spawn(task_main_loop(
+        ctx.clone(),
+        iroh_gossip.clone(),
+        gossip_topic,
+        bootstrap_nodes,
+        outbound_queue_rx,
+        leave_rx,
+        first_autosave,
+    ));
+
+    let session = Session {
+        ctx,
+        iroh_gossip,
+        iroh_router,
+        _loro_sub: loro_sub,
+        main_loop_handle,
+        leave_tx,
+    };
+
+    if is_joining {
+        options.report(SetupStage::Syncing);
+        select! {
+            result = timeout(SYNC_TIMEOUT, synced(&session.ctx, &mut events)) => {
+                if result.is_err() {
+                    eprintln!("Document not synced after {SYNC_TIMEOUT:?}, continuing in the background");
+                }
+            }
+            _ = cancelled(&mut cancel) => {
+                // Nothing of a join given up on should land in the recent documents
+                session.shut_down().await;
+                return Err(SetupError::Cancelled);
+            }
+        }
+    }
+
+    Ok(session)
+}
+
+/// Resolves once a peer's awareness shows it has nothing our document lacks. The
+/// main loop asks for missing updates as soon as it has a neighbor.
+async fn synced(ctx: &SessionContext, events: &mut broadcast::Receiver<SessionEvent>) {
+    loop {
+        let own_version = ctx.loro_doc.oplog_vv();
+        let up_to_date = ctx
+            .presence
+            .lock()
+            .awareness_cache
+            .values()
+            .any(|(awareness, _)| {
+                VersionVector::decode(&awareness.version)
+                    .is_ok_and(|their_version| own_version.includes_vv(&their_version))
+            });
+        if up_to_date {
+            return;
+        }
+
+        match events.recv().await {
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+use anyhow::{Result, anyhow, bail};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, aead::Aead};
+
+const NONCE_SIZE: usize = 12;
+
+/// Secret generated when a session is created and shared only through its ticket.
+pub type SessionSecret = [u8; 32];
+
+/// Encrypts and authenticates payloads with the session secret. Each sealed payload
+/// is a fresh random nonce followed by the ciphertext.
+#[derive(Clone)]
+pub struct SessionCipher(ChaCha20Poly1305);
+
+impl SessionCipher {
+    pub fn new(secret: &SessionSecret) -> Self {
+        Self(ChaCha20Poly1305::new(Key::from_slice(secret)))
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let ciphertext = self
+            .0
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow!("Failed to encrypt payload"))?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_SIZE {
+            bail!("Encrypted payload too short");
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        self.0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt payload"))
+    }
+}
+
+impl fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionCipher(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_payload_opens_with_the_same_secret() {
+        let cipher = SessionCipher::new(&[7; 32]);
+
+        let sealed = cipher.seal(b"hello").unwrap();
+
+        assert_ne!(&sealed[NONCE_SIZE..], b"hello");
+        assert_eq!(cipher.open(&sealed).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn every_seal_uses_a_fresh_nonce() {
+        let cipher = SessionCipher::new(&[7; 32]);
+
+        assert_ne!(
+            cipher.seal(b"hello").unwrap(),
+            cipher.seal(b"hello").unwrap()
+        );
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let cipher = SessionCipher::new(&[7; 32]);
+        let sealed = cipher.seal(b"hello").unwrap();
+
+        for index in [0, NONCE_SIZE, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 1;
+            assert!(cipher.open(&tampered).is_err(), "flipped byte {index}");
+        }
+        assert!(cipher.open(&sealed[..sealed.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn other_secret_cannot_open() {
+        let sealed = SessionCipher::new(&[7; 32]).seal(b"hello").unwrap();
+
+        assert!(SessionCipher::new(&[8; 32]).open(&sealed).is_err());
+    }
+
+    #[test]
+    fn short_payload_is_rejected() {
+        let cipher = SessionCipher::new(&[7; 32]);
+
+        assert!(cipher.open(&[0; NONCE_SIZE - 1]).is_err());
+        assert!(cipher.open(&[0; NONCE_SIZE]).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::Result;
+use iroh::EndpointId;
+use iroh_gossip::{
+    Gossip,
+    api::{Event, GossipReceiver, GossipSender, GossipTopic},
+};
+use tokio::{
+    select,
+    sync::{mpsc::UnboundedReceiver, oneshot},
+    time::{Instant, interval, interval_at, sleep, sleep_until},
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    awareness::{awareness_refresh, unix_time_ms},
+    document_store,
+    gossip_message::{GossipMessage, handle_gossip_message, request_missing, send_unacknowledged},
+    protocol::Envelope,
+    session::{SessionContext, SessionEvent},
+};
+
+const AWARENESS_PERIOD: Duration = Duration::from_millis(500);
+pub(crate) const AUTOSAVE_PERIOD: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const LEAVE_GRACE_PERIOD: Duration = Duration::from_millis(300);
+/// Failed rejoin attempts after which the session reports itself offline.
+const OFFLINE_AFTER_ATTEMPTS: u32 = 5;
+
+/// How well we are connected to the rest of the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionStatus {
+    /// No neighbor has shown up yet.
+    #[default]
+    Waiting,
+    Connected,
+    /// Every neighbor was lost; rejoining known peers with backoff.
+    Reconnecting,
+    /// Rejoining keeps failing. Retries continue at the slowest pace.
+    Offline,
+}
+
+/// Rejoin schedule while we have no gossip neighbors.
+struct Reconnect {
+    attempts: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Reconnect {
+    fn start(&mut self) {
+        self.attempts = 0;
+        self.retry_at = Some(Instant::now() + INITIAL_BACKOFF);
+    }
+
+    fn stop(&mut self) {
+        self.attempts = 0;
+        self.retry_at = None;
+    }
+
+    fn schedule_next(&mut self) {
+        self.attempts += 1;
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(MAX_BACKOFF);
+        self.retry_at = Some(Instant::now() + backoff);
+    }
+}
+
+fn set_status(ctx: &SessionContext, status: ConnectionStatus) {
+    let changed = {
+        let mut current = ctx.status.lock();
+        std::mem::replace(&mut *current, status) != status
+    };
+
+    if changed {
+        ctx.emit(SessionEvent::StatusChanged);
+    }
+}
+
+/// Everyone we could try to rejoin through: peers from the ticket, former neighbors
+/// and whoever we have heard from through awareness.
+fn rejoin_candidates(ctx: &SessionContext, known_peers: &HashSet<EndpointId>) -> Vec<EndpointId> {
+    let mut candidates = known_peers.clone();
+    candidates.extend(
+        ctx.presence
+            .lock()
+            .awareness_cache
+            .keys()
+            .filter_map(|id_bytes| EndpointId::from_bytes(id_bytes).ok()),
+    );
+
+    let message_errors = ctx.message_errors.lock();
+    candidates
+        .into_iter()
+        .filter(|peer| !message_errors.blocked.contains(peer))
+        .collect()
+}
+
+fn handle_received(ctx: &SessionContext, content: &[u8]) -> Result<()> {
+    // Peers without the session secret cannot read or forge messages
+    let plaintext = ctx.cipher.open(content)?;
+    let envelope = Envelope::decode(&plaintext)?;
+
+    let protocol = envelope.protocol();
+    let previous = ctx
+        .presence
+        .lock()
+        .peer_protocols
+        .insert(envelope.sender, (protocol, std::time::Instant::now()));
+    if previous.map(|(previous, _)| previous) != Some(protocol) {
+        ctx.emit(SessionEvent::PresenceChanged);
+    }
+
+    // Mixed-version sessions keep working between compatible peers; the rest is
+    // only surfaced as a warning
+    if !protocol.is_compatible() {
+        return Ok(());
+    }
+
+    match envelope.payload()? {
+        Some(gossip_message) => handle_gossip_message(gossip_message, ctx),
+        None => Ok(()),
+    }
+}
+
+async fn broadcast(
+    ctx: &SessionContext,
+    sender: &GossipSender,
+    message: &GossipMessage,
+) -> Result<()> {
+    let bytes = ctx.cipher.seal(&Envelope::encode(ctx.own_id, message)?)?;
+    sender.broadcast(bytes.into()).await?;
+
+    Ok(())
+}
+
+/// Exchanges whatever was missed in either direction while we had nobody to talk to:
+/// asks for updates made elsewhere and replays our own that no peer acknowledged.
+fn catch_up(ctx: &SessionContext, receiver: &GossipReceiver) {
+    request_missing(ctx);
+    if let Err(err) = send_unacknowledged(ctx, receiver.neighbors()) {
+        eprintln!("Failed to replay unacknowledged updates: {err:#}");
+    }
+}
+
+/// Runs the session's networking until asked to leave or aborted: relays gossip in both
+/// directions, refreshes awareness, autosaves, and rejoins known peers whenever every
+/// neighbor is lost. Individual failures are logged and never end the loop. The first
+/// autosave happens after `first_autosave`.
+pub(crate) async fn task_main_loop(
+    ctx: SessionContext,
+    iroh_gossip: Gossip,
+    gossip_topic: GossipTopic,
+    bootstrap_nodes: Vec<EndpointId>,
+    mut outbound_queue_rx: UnboundedReceiver<GossipMessage>,
+    mut leave_rx: oneshot::Receiver<()>,
+    first_autosave: Duration,
+) {
+    let mut known_peers: HashSet<EndpointId> = bootstrap_nodes.into_iter().collect();
+    let (mut sender, mut receiver) = gossip_topic.split();
+    let mut awareness_interval = interval(AWARENESS_PERIOD);
+    let mut autosave_interval = interval_at(Instant::now() + first_autosave, AUTOSAVE_PERIOD);
+    let mut reconnect = Reconnect {
+        attempts: 0,
+        retry_at: None,
+    };
+
+    if receiver.is_joined() {
+        known_peers.extend(receiver.neighbors());
+        catch_up(&ctx, &receiver);
+        set_status(&ctx, ConnectionStatus::Connected);
+    } else if !known_peers.is_empty() {
+        reconnect.start();
+    }
+
+    loop {
+        select! {
+            event = receiver.next() => match event {
+                Some(Ok(Event::Received(message))) => {
+                    let sender_id = message.delivered_from;
+                    if ctx.message_errors.lock().blocked.contains(&sender_id) {
+                        continue;
+                    }
+
+                    if let Err(err) = handle_received(&ctx, &message.content) {
+                        eprintln!("Dropping message from {sender_id}: {err:#}");
+                        let blocked = ctx
+                            .message_errors
+                            .lock()
+                            .record(sender_id, ctx.blocklist_after);
+                        if blocked {
+                            eprintln!("Blocking {sender_id} after repeated bad messages");
+                        }
+                        ctx.emit(SessionEvent::MessageRejected);
+                    }
+                }
+                Some(Ok(Event::NeighborUp(endpoint_id))) => {
+                    known_peers.insert(endpoint_id);
+                    let was_connected = *ctx.status.lock() == ConnectionStatus::Connected;
+                    if !was_connected {
+                        catch_up(&ctx, &receiver);
+                    }
+                    reconnect.stop();
+                    set_status(&ctx, ConnectionStatus::Connected);
+                }
+                Some(Ok(Event::NeighborDown(_))) => {
+                    if !receiver.is_joined() {
+                        eprintln!("Lost every gossip neighbor, reconnecting");
+                        reconnect.start();
+                        set_status(&ctx, ConnectionStatus::Reconnecting);
+                    }
+                }
+                Some(Ok(Event::Lagged)) => {
+                    // Messages were skipped, so ask for the updates they may have carried
+                    request_missing(&ctx);
+                }
+                Some(Err(err)) => eprintln!("Gossip error: {err:#}"),
+                None => {
+                    // The subscription ended underneath us; subscribe again from scratch
+                    let peers = rejoin_candidates(&ctx, &known_peers);
+                    match iroh_gossip.subscribe(ctx.topic_id, peers).await {
+                        Ok(gossip_topic) => {
+                            (sender, receiver) = gossip_topic.split();
+                            reconnect.start();
+                            set_status(&ctx, ConnectionStatus::Reconnecting);
+                        }
+                        Err(err) => {
+                            eprintln!("Gossip subscription closed: {err:#}");
+                            set_status(&ctx, ConnectionStatus::Offline);
+                            return;
+                        }
+                    }
+                }
+            },
+            Some(message) = outbound_queue_rx.recv() => {
+                if let Err(err) = broadcast(&ctx, &sender, &message).await {
+                    eprintln!("Failed to broadcast message: {err:#}");
+                }
+            }
+            _ = sleep_until(reconnect.retry_at.unwrap_or_else(Instant::now)), if reconnect.retry_at.is_some() => {
+                let peers = rejoin_candidates(&ctx, &known_peers);
+                if let Err(err) = sender.join_peers(peers).await {
+                    eprintln!("Failed to rejoin peers: {err:#}");
+                }
+                reconnect.schedule_next();
+
+                if reconnect.attempts >= OFFLINE_AFTER_ATTEMPTS
+                    && *ctx.status.lock() == ConnectionStatus::Reconnecting
+                {
+                    set_status(&ctx, ConnectionStatus::Offline);
+                }
+            }
+            Ok(()) = &mut leave_rx => {
+                // Flush edits that are still queued, then say goodbye
+                while let Ok(message) = outbound_queue_rx.try_recv() {
+                    if let Err(err) = broadcast(&ctx, &sender, &message).await {
+                        eprintln!("Failed to broadcast message: {err:#}");
+                    }
+                }
+
+                let leave = GossipMessage::Leave {
+                    endpoint_id: ctx.own_id,
+                    timestamp_ms: unix_time_ms(),
+                };
+                if let Err(err) = broadcast(&ctx, &sender, &leave).await {
+                    eprintln!("Failed to announce leaving: {err:#}");
+                }
+                // Broadcasting only hands the message to the gossip actor, so give it
+                // a moment to reach neighbors before the session shuts gossip down
+                sleep(LEAVE_GRACE_PERIOD).await;
+                return;
+            }
+            _ = awareness_interval.tick() => {
+                if let Err(err) = awareness_refresh(&ctx) {
+                    eprintln!("Awareness refresh failed: {err:#}");
+                }
+            }
+            _ = autosave_interval.tick() => {
+                let _ = document_store::save_document(&ctx);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::{Endpoint, RelayMode};
+    use iroh_gossip::TopicId;
+    use loro::LoroDoc;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::{
+        awareness::broadcast_awareness,
+        profile::Profile,
+        rich_text,
+        session_crypto::SessionCipher,
+        session_ticket::SessionTicket,
+        workspace::{self, MAIN_DOCUMENT},
+    };
+
+    struct Peer {
+        ctx: SessionContext,
+        outbound: UnboundedReceiver<GossipMessage>,
+        _loro_sub: loro::Subscription,
+    }
+
+    impl Peer {
+        async fn new(name: &str, ticket: &SessionTicket) -> Peer {
+            let loro_doc = LoroDoc::new();
+            rich_text::configure_text_styles(&loro_doc);
+            let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+                .bind()
+                .await
+                .unwrap();
+            let (ctx, outbound) = SessionContext::new(
+                Profile::new(name.to_string()),
+                ticket,
+                SessionCipher::new(&ticket.secret),
+                loro_doc,
+                endpoint,
+                None,
+            );
+            let _loro_sub = ctx.subscribe_local_updates();
+
+            Peer {
+                ctx,
+                outbound,
+                _loro_sub,
+            }
+        }
+
+        fn text(&self) -> String {
+            workspace::document_text(&self.ctx.loro_doc, MAIN_DOCUMENT).to_string()
+        }
+
+        fn type_text(&self, text: &str) {
+            let doc_text = workspace::document_text(&self.ctx.loro_doc, MAIN_DOCUMENT);
+            rich_text::update_text(&doc_text, text).unwrap();
+            self.ctx.loro_doc.commit();
+        }
+
+        /// Hands everything this peer queued to `other`, sealed and wrapped the way
+        /// the main loop broadcasts it.
+        fn deliver_to(&mut self, other: &Peer) {
+            while let Ok(message) = self.outbound.try_recv() {
+                let envelope = Envelope::encode(self.ctx.own_id, &message).unwrap();
+                let sealed = self.ctx.cipher.seal(&envelope).unwrap();
+                handle_received(&other.ctx, &sealed).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn joining_peer_syncs_and_edits_flow_both_ways() {
+        let ticket = SessionTicket {
+            topic_id: TopicId::from_bytes(rand::random()),
+            secret: rand::random(),
+            peers: vec![],
+        };
+        let mut alice = Peer::new("Alice", &ticket).await;
+        alice.type_text("hello");
+        // Nobody was there to hear it
+        while alice.outbound.try_recv().is_ok() {}
+
+        // Joining asks for what we lack, and the only other peer answers
+        let mut bob = Peer::new("Bob", &ticket).await;
+        request_missing(&bob.ctx);
+        bob.deliver_to(&alice);
+        alice.deliver_to(&bob);
+        assert_eq!(bob.text(), "hello");
+
+        broadcast_awareness(&bob.ctx).unwrap();
+        bob.deliver_to(&alice);
+        {
+            let presence = alice.ctx.presence.lock();
+            assert!(presence.awareness_cache.contains_key(&bob.ctx.own_id));
+            assert!(presence.peer_protocols.contains_key(&bob.ctx.own_id));
+        }
+
+        bob.type_text("hello world");
+        bob.deliver_to(&alice);
+        assert_eq!(alice.text(), "hello world");
+
+        // Concurrent edits merge the same way on both sides
+        alice.type_text("Hello world");
+        bob.type_text("hello world!");
+        alice.deliver_to(&bob);
+        bob.deliver_to(&alice);
+        assert_eq!(alice.text(), "Hello world!");
+        assert_eq!(bob.text(), "Hello world!");
+
+        alice.ctx.iroh_endpoint.close().await;
+        bob.ctx.iroh_endpoint.close().await;
+    }
+}
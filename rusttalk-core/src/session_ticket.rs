@@ -0,0 +1,97 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result};
+use data_encoding::BASE32_NOPAD;
+use iroh::EndpointAddr;
+use iroh_gossip::TopicId;
+use postcard::{from_bytes, to_allocvec as to_bytes};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::session_crypto::SessionSecret;
+
+const TICKET_PREFIX: &str = "collab";
+
+/// Everything a peer needs to join a session: the gossip topic, the secret that
+/// encrypts its traffic, and the addresses (relay URL and direct addresses) of
+/// peers already in it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionTicket {
+    pub topic_id: TopicId,
+    pub secret: SessionSecret,
+    pub peers: Vec<EndpointAddr>,
+}
+
+impl fmt::Display for SessionTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = to_bytes(self).map_err(|_| fmt::Error)?;
+        let encoded = BASE32_NOPAD.encode(&bytes).to_ascii_lowercase();
+        write!(f, "{TICKET_PREFIX}{encoded}")
+    }
+}
+
+impl FromStr for SessionTicket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let encoded = s
+            .trim()
+            .strip_prefix(TICKET_PREFIX)
+            .context("Session ticket must start with \"collab\"")?;
+        let bytes = BASE32_NOPAD
+            .decode(encoded.to_ascii_uppercase().as_bytes())
+            .context("Session ticket is not valid base32")?;
+        let ticket: SessionTicket = from_bytes(&bytes).context("Session ticket is malformed")?;
+
+        Ok(ticket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::{RelayUrl, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn ticket_roundtrips_through_its_text_form() {
+        let endpoint_id = SecretKey::from_bytes(&[3; 32]).public();
+        let relay_url: RelayUrl = "https://relay.example.com".parse().unwrap();
+        let ticket = SessionTicket {
+            topic_id: TopicId::from_bytes([1; 32]),
+            secret: [2; 32],
+            peers: vec![
+                EndpointAddr::new(endpoint_id)
+                    .with_relay_url(relay_url)
+                    .with_ip_addr("192.0.2.1:4433".parse().unwrap()),
+            ],
+        };
+
+        let text = ticket.to_string();
+        assert!(text.starts_with(TICKET_PREFIX));
+        assert!(!text.chars().any(|c| c.is_ascii_uppercase()));
+
+        // Pasted tickets often carry surrounding whitespace
+        let parsed: SessionTicket = format!("  {text}\n").parse().unwrap();
+        assert_eq!(parsed.topic_id, ticket.topic_id);
+        assert_eq!(parsed.secret, ticket.secret);
+        assert_eq!(parsed.peers, ticket.peers);
+    }
+
+    #[test]
+    fn malformed_tickets_are_rejected() {
+        let text = SessionTicket {
+            topic_id: TopicId::from_bytes([1; 32]),
+            secret: [2; 32],
+            peers: vec![],
+        }
+        .to_string();
+
+        assert!(
+            text.trim_start_matches(TICKET_PREFIX)
+                .parse::<SessionTicket>()
+                .is_err()
+        );
+        assert!("collab!!!".parse::<SessionTicket>().is_err());
+        assert!(text[..text.len() - 8].parse::<SessionTicket>().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::{error::Error, fmt, time::Duration};
+
+use iroh::SecretKey;
+use tokio::sync::{oneshot, watch};
+
+pub const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Knobs and hooks for [`Session::start_with`](crate::Session::start_with).
+pub struct SetupOptions {
+    /// How long `Join` waits for a peer from the ticket to answer.
+    pub join_timeout: Duration,
+    /// Receives each stage as setup reaches it.
+    pub progress: Option<watch::Sender<SetupStage>>,
+    /// Sending on the other end abandons setup and tears down what was built so far.
+    pub cancel: Option<oneshot::Receiver<()>>,
+    /// Stop reading messages from a neighbor after this many of its messages failed.
+    /// `None` only counts failures.
+    pub blocklist_after: Option<u32>,
+    /// Identity to bind the endpoint with, usually from [`identity::load_or_create`](crate::identity::load_or_create).
+    /// `None` uses a throwaway one.
+    pub secret_key: Option<SecretKey>,
+}
+
+impl Default for SetupOptions {
+    fn default() -> Self {
+        Self {
+            join_timeout: DEFAULT_JOIN_TIMEOUT,
+            progress: None,
+            cancel: None,
+            blocklist_after: None,
+            secret_key: None,
+        }
+    }
+}
+
+impl SetupOptions {
+    pub(crate) fn report(&self, stage: SetupStage) {
+        if let Some(progress) = &self.progress {
+            let _ = progress.send(stage);
+        }
+    }
+}
+
+/// Resolves once setup has been cancelled; never, if it cannot be.
+pub(crate) async fn cancelled(cancel: &mut Option<oneshot::Receiver<()>>) {
+    if let Some(receiver) = cancel {
+        let result = receiver.await;
+        // A finished receiver must not be polled again
+        *cancel = None;
+        if result.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupStage {
+    Binding,
+    Dialing,
+    Subscribing,
+    Syncing,
+}
+
+impl fmt::Display for SetupStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupStage::Binding => write!(f, "Opening a network endpoint…"),
+            SetupStage::Dialing => write!(f, "Dialing peers from the ticket…"),
+            SetupStage::Subscribing => write!(f, "Waiting for a peer to let us in…"),
+            SetupStage::Syncing => write!(f, "Syncing the document…"),
+        }
+    }
+}
+
+/// Why a session could not be started, in terms the person in the lobby can act on.
+#[derive(Debug)]
+pub enum SetupError {
+    InvalidTicket(anyhow::Error),
+    /// Nobody from the ticket answered before the join timeout.
+    JoinTimeout(Duration),
+    Bind(anyhow::Error),
+    Subscribe(anyhow::Error),
+    /// The locally stored copy of the document could not be read.
+    Storage(anyhow::Error),
+    Cancelled,
+}
+
+impl SetupError {
+    /// What to try next.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            SetupError::InvalidTicket(_) => {
+                "Check that the whole ticket was copied, starting with \"collab\"."
+            }
+            SetupError::JoinTimeout(_) => {
+                "Make sure someone in the session is online, or ask them for a fresh ticket."
+            }
+            SetupError::Bind(_) => "Check your network connection and firewall, then try again.",
+            SetupError::Subscribe(_) => "Try again. If it keeps failing, restart the app.",
+            SetupError::Storage(_) => "Check that the app's data directory is readable.",
+            SetupError::Cancelled => "",
+        }
+    }
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::InvalidTicket(_) => write!(f, "That session ticket is not valid"),
+            SetupError::JoinTimeout(timeout) => write!(
+                f,
+                "No peer from the ticket answered within {} seconds",
+                timeout.as_secs()
+            ),
+            SetupError::Bind(_) => write!(f, "Could not open a network endpoint"),
+            SetupError::Subscribe(_) => write!(f, "Could not join the session's gossip topic"),
+            SetupError::Storage(_) => write!(f, "Could not load the stored copy of this document"),
+            SetupError::Cancelled => write!(f, "Setup was cancelled"),
+        }
+    }
+}
+
+impl Error for SetupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SetupError::InvalidTicket(err)
+            | SetupError::Bind(err)
+            | SetupError::Subscribe(err)
+            | SetupError::Storage(err) => Some(err.as_ref()),
+            SetupError::JoinTimeout(_) | SetupError::Cancelled => None,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::{fs, io::Write, path::Path};
+
+use anyhow::Result;
+
+/// Who may read a file written with [`write_atomic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// The usual permissions for new files.
+    Shared,
+    /// Only the owner, from the moment the file exists.
+    Private,
+}
+
+/// Writes through a temporary file so a crash mid-write never corrupts the old copy.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8], access: Access) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    // A leftover temp file keeps whatever permissions it had, so always start anew
+    let _ = fs::remove_file(&tmp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if access == Access::Private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+
+    fs::rename(tmp_path, path)?;
+
+    Ok(())
+}
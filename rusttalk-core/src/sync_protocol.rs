@@ -0,0 +1,154 @@
+use anyhow::{Result, bail};
+use iroh::{
+    Endpoint, EndpointId,
+    endpoint::Connection,
+    protocol::{AcceptError, ProtocolHandler},
+};
+use iroh_gossip::TopicId;
+use loro::{LoroDoc, VersionVector};
+use postcard::{from_bytes, to_allocvec as to_bytes};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    session::{SessionContext, SessionEvent},
+    session_crypto::SessionCipher,
+};
+
+/// Direct QUIC protocol for transferring document updates too large for a gossip message.
+pub const ALPN: &[u8] = b"rusty-collab/sync/0";
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+const MAX_HEADER_SIZE: usize = 1024;
+const MAX_TRANSFER_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct SyncRequest {
+    topic_id: TopicId,
+    version: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncHeader {
+    len: u64,
+    hash: [u8; 32],
+}
+
+/// Serves the updates a requesting peer is missing, streamed in chunks. Both the
+/// request and the transfer are sealed with the session cipher, so only peers holding
+/// the session secret get anything readable.
+#[derive(Debug, Clone)]
+pub struct SyncProtocol {
+    pub topic_id: TopicId,
+    pub cipher: SessionCipher,
+    pub loro_doc: LoroDoc,
+}
+
+impl SyncProtocol {
+    async fn serve(&self, connection: Connection) -> Result<()> {
+        let (mut send, mut recv) = connection.accept_bi().await?;
+
+        let sealed_request = recv.read_to_end(MAX_REQUEST_SIZE).await?;
+        let request: SyncRequest = from_bytes(&self.cipher.open(&sealed_request)?)?;
+        if request.topic_id != self.topic_id {
+            bail!("Sync requested for unknown topic {}", request.topic_id);
+        }
+
+        let their_version = VersionVector::decode(&request.version)?;
+        let updates = self
+            .loro_doc
+            .export(loro::ExportMode::updates(&their_version))?;
+        let updates = self.cipher.seal(&updates)?;
+
+        let header = to_bytes(&SyncHeader {
+            len: updates.len() as u64,
+            hash: *blake3::hash(&updates).as_bytes(),
+        })?;
+        send.write_all(&(header.len() as u32).to_be_bytes()).await?;
+        send.write_all(&header).await?;
+        for chunk in updates.chunks(CHUNK_SIZE) {
+            send.write_all(chunk).await?;
+        }
+        send.finish()?;
+
+        // Keep the connection open until the requester has read everything
+        connection.closed().await;
+
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for SyncProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.serve(connection)
+            .await
+            .map_err(|err| AcceptError::from_boxed(err.into()))
+    }
+}
+
+/// Fetches everything `peer` has that `loro_doc` is missing and imports it.
+pub async fn fetch_updates(
+    endpoint: &Endpoint,
+    peer: EndpointId,
+    topic_id: TopicId,
+    cipher: &SessionCipher,
+    loro_doc: &LoroDoc,
+) -> Result<()> {
+    let connection = endpoint.connect(peer, ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+
+    let request = to_bytes(&SyncRequest {
+        topic_id,
+        version: loro_doc.oplog_vv().encode(),
+    })?;
+    send.write_all(&cipher.seal(&request)?).await?;
+    send.finish()?;
+
+    let mut header_len = [0u8; 4];
+    recv.read_exact(&mut header_len).await?;
+    let header_len = u32::from_be_bytes(header_len) as usize;
+    if header_len > MAX_HEADER_SIZE {
+        bail!("Sync header too large: {header_len} bytes");
+    }
+    let mut header = vec![0u8; header_len];
+    recv.read_exact(&mut header).await?;
+    let header: SyncHeader = from_bytes(&header)?;
+    if header.len > MAX_TRANSFER_SIZE {
+        bail!("Sync transfer too large: {} bytes", header.len);
+    }
+
+    // Reassemble the chunks, then check integrity before touching the document
+    let mut updates = Vec::with_capacity(header.len as usize);
+    while (updates.len() as u64) < header.len {
+        let remaining = header.len as usize - updates.len();
+        let Some(chunk) = recv.read_chunk(remaining.min(CHUNK_SIZE)).await? else {
+            bail!("Sync stream ended early");
+        };
+        updates.extend_from_slice(&chunk.bytes);
+    }
+    if blake3::hash(&updates).as_bytes() != &header.hash {
+        bail!("Sync transfer failed integrity check");
+    }
+
+    connection.close(0u32.into(), b"done");
+    loro_doc.import(&cipher.open(&updates)?)?;
+
+    Ok(())
+}
+
+pub(crate) async fn task_direct_sync(ctx: SessionContext, peer: EndpointId) {
+    if let Err(err) = fetch_updates(
+        &ctx.iroh_endpoint,
+        peer,
+        ctx.topic_id,
+        &ctx.cipher,
+        &ctx.loro_doc,
+    )
+    .await
+    {
+        eprintln!("Direct sync with {peer} failed: {err:#}");
+        return;
+    }
+
+    ctx.emit(SessionEvent::DocumentChanged);
+}
@@ -0,0 +1,252 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail};
+use loro::{CommitOptions, ContainerTrait, Frontiers, LoroDoc, LoroText, LoroValue, event::Diff};
+
+/// Identifies a document within a session's workspace. Ids start with the creation
+/// time, so sorting them lists documents in the order they were created.
+pub type DocumentId = String;
+
+/// The document every session starts with. Its text lives in the root `"text"`
+/// container that single-document sessions used, so older documents open unchanged.
+pub const MAIN_DOCUMENT: &str = "main";
+
+const DEFAULT_TITLE: &str = "Untitled";
+
+/// Origin of commits that change the workspace rather than a document's text, so
+/// undo can leave them out.
+pub const WORKSPACE_ORIGIN: &str = "workspace";
+
+/// Root map from document id to title. A document exists while it has an entry;
+/// titles are plain values so concurrent renames resolve last-writer-wins.
+const DOCUMENTS_MAP: &str = "documents";
+
+/// Root map from document id to its format. Documents without an entry are rich
+/// text, which is what every document was before formats existed.
+const FORMATS_MAP: &str = "document_formats";
+
+/// How a document's text is meant to be read, shared by everyone in the session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DocumentFormat {
+    /// Plain text with Loro marks for styling.
+    #[default]
+    RichText,
+    /// Markdown source, shown highlighted next to a rendered preview.
+    Markdown,
+}
+
+impl DocumentFormat {
+    fn key(self) -> &'static str {
+        match self {
+            DocumentFormat::RichText => "rich_text",
+            DocumentFormat::Markdown => "markdown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentInfo {
+    pub id: DocumentId,
+    pub title: String,
+    pub format: DocumentFormat,
+}
+
+/// Text container of a document. Each document gets its own root container, named
+/// after its id, so peers creating documents concurrently can never clash.
+pub fn document_text(loro_doc: &LoroDoc, id: &str) -> LoroText {
+    if id == MAIN_DOCUMENT {
+        loro_doc.get_text("text")
+    } else {
+        loro_doc.get_text(format!("text-{id}"))
+    }
+}
+
+/// Every document in the workspace, the main one first and the rest by creation.
+pub fn documents(loro_doc: &LoroDoc) -> Vec<DocumentInfo> {
+    let documents = loro_doc.get_map(DOCUMENTS_MAP);
+    let mut infos = Vec::new();
+    documents.for_each(|id, value| {
+        if id == MAIN_DOCUMENT {
+            return;
+        }
+        infos.push(DocumentInfo {
+            id: id.to_string(),
+            title: title_from(value.into_value().ok()),
+            format: format(loro_doc, id),
+        });
+    });
+    infos.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let main_title = documents
+        .get(MAIN_DOCUMENT)
+        .and_then(|value| value.into_value().ok());
+    infos.insert(
+        0,
+        DocumentInfo {
+            id: MAIN_DOCUMENT.to_string(),
+            title: title_from(main_title),
+            format: format(loro_doc, MAIN_DOCUMENT),
+        },
+    );
+
+    infos
+}
+
+fn title_from(value: Option<LoroValue>) -> String {
+    match value {
+        Some(LoroValue::String(title)) if !title.trim().is_empty() => title.to_string(),
+        _ => DEFAULT_TITLE.to_string(),
+    }
+}
+
+pub fn format(loro_doc: &LoroDoc, id: &str) -> DocumentFormat {
+    match loro_doc
+        .get_map(FORMATS_MAP)
+        .get(id)
+        .and_then(|value| value.into_value().ok())
+    {
+        Some(LoroValue::String(key)) if *key == DocumentFormat::Markdown.key() => {
+            DocumentFormat::Markdown
+        }
+        // Formats from newer builds fall back to rich text, which shows any text
+        _ => DocumentFormat::RichText,
+    }
+}
+
+pub fn exists(loro_doc: &LoroDoc, id: &str) -> bool {
+    id == MAIN_DOCUMENT || loro_doc.get_map(DOCUMENTS_MAP).get(id).is_some()
+}
+
+fn commit(loro_doc: &LoroDoc) {
+    loro_doc.commit_with(CommitOptions::new().origin(WORKSPACE_ORIGIN));
+}
+
+pub(crate) fn create(loro_doc: &LoroDoc, title: &str) -> Result<DocumentId> {
+    let created_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let id = format!("{created_ms:013}-{:08x}", rand::random::<u32>());
+
+    loro_doc.get_map(DOCUMENTS_MAP).insert(&id, title)?;
+    commit(loro_doc);
+
+    Ok(id)
+}
+
+pub(crate) fn rename(loro_doc: &LoroDoc, id: &str, title: &str) -> Result<()> {
+    if !exists(loro_doc, id) {
+        bail!("No document {id} in this session");
+    }
+
+    loro_doc.get_map(DOCUMENTS_MAP).insert(id, title)?;
+    commit(loro_doc);
+
+    Ok(())
+}
+
+pub(crate) fn set_format(loro_doc: &LoroDoc, id: &str, format: DocumentFormat) -> Result<()> {
+    if !exists(loro_doc, id) {
+        bail!("No document {id} in this session");
+    }
+
+    loro_doc.get_map(FORMATS_MAP).insert(id, format.key())?;
+    commit(loro_doc);
+
+    Ok(())
+}
+
+/// Puts a document's text and formatting back the way they were at `frontiers`, as
+/// a new edit. Titles, formats and the other documents stay as they are.
+pub(crate) fn restore(loro_doc: &LoroDoc, id: &str, frontiers: &Frontiers) -> Result<()> {
+    let doc_text = document_text(loro_doc, id);
+    let diff = loro_doc.diff(&loro_doc.state_frontiers(), frontiers)?;
+    let deltas = diff.iter().find_map(|(container_id, diff)| match diff {
+        Diff::Text(deltas) if *container_id == doc_text.id() => Some(deltas.clone()),
+        _ => None,
+    });
+
+    if let Some(deltas) = deltas {
+        doc_text.apply_delta(&deltas)?;
+        loro_doc.commit();
+    }
+
+    Ok(())
+}
+
+/// Removes a document from the workspace. Its text stays in the history, so
+/// restoring an older version brings it back.
+pub(crate) fn delete(loro_doc: &LoroDoc, id: &str) -> Result<()> {
+    if id == MAIN_DOCUMENT {
+        bail!("The main document cannot be deleted");
+    }
+
+    loro_doc.get_map(DOCUMENTS_MAP).delete(id)?;
+    commit(loro_doc);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rich_text::{self, TextStyle, styled_spans};
+
+    #[test]
+    fn undo_leaves_workspace_changes_alone() {
+        let loro_doc = LoroDoc::new();
+        let mut undo_manager = loro::UndoManager::new(&loro_doc);
+        undo_manager.add_exclude_origin_prefix(WORKSPACE_ORIGIN);
+
+        document_text(&loro_doc, MAIN_DOCUMENT)
+            .insert(0, "hello")
+            .unwrap();
+        loro_doc.commit();
+        let notes = create(&loro_doc, "Notes").unwrap();
+        set_format(&loro_doc, &notes, DocumentFormat::Markdown).unwrap();
+
+        assert!(undo_manager.undo().unwrap());
+        assert_eq!(document_text(&loro_doc, MAIN_DOCUMENT).to_string(), "");
+        assert!(exists(&loro_doc, &notes));
+        assert_eq!(format(&loro_doc, &notes), DocumentFormat::Markdown);
+        assert!(!undo_manager.undo().unwrap());
+    }
+
+    #[test]
+    fn restore_only_touches_the_document() {
+        let loro_doc = LoroDoc::new();
+        rich_text::configure_text_styles(&loro_doc);
+        let notes = create(&loro_doc, "Notes").unwrap();
+        let main_text = document_text(&loro_doc, MAIN_DOCUMENT);
+        main_text.insert(0, "hello world").unwrap();
+        rich_text::toggle_style(&main_text, 0..5, TextStyle::Bold).unwrap();
+        document_text(&loro_doc, &notes).insert(0, "old").unwrap();
+        loro_doc.commit();
+        let frontiers = loro_doc.oplog_frontiers();
+
+        main_text.delete(5, 6).unwrap();
+        rich_text::toggle_style(&main_text, 0..5, TextStyle::Bold).unwrap();
+        main_text.insert(5, "!").unwrap();
+        document_text(&loro_doc, &notes)
+            .insert(3, " and new")
+            .unwrap();
+        rename(&loro_doc, &notes, "Renamed").unwrap();
+        let later = create(&loro_doc, "Later").unwrap();
+        loro_doc.commit();
+
+        restore(&loro_doc, MAIN_DOCUMENT, &frontiers).unwrap();
+
+        assert_eq!(main_text.to_string(), "hello world");
+        let spans = styled_spans(&main_text);
+        assert_eq!(spans[0].range, 0..5);
+        assert!(spans[0].attributes.bold);
+        assert!(!spans[1].attributes.bold);
+        assert_eq!(document_text(&loro_doc, &notes).to_string(), "old and new");
+        assert!(exists(&loro_doc, &later));
+        assert!(
+            documents(&loro_doc)
+                .iter()
+                .any(|document| document.title == "Renamed")
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use loro::VersionVector;
+use rusttalk_core::{
+    Profile, Session, SessionEvent, SessionStart, SetupOptions, identity,
+    workspace::{self, MAIN_DOCUMENT},
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    select, signal,
+    sync::broadcast::error::RecvError,
+    time::{Instant, sleep_until, timeout},
+};
+
+const USAGE: &str = "\
+Usage:
+  rusttalk                              Start the desktop app
+  rusttalk create [--name NAME]         Create a session, print its ticket and stream the document
+  rusttalk join TICKET [--name NAME] [--join-timeout SECS]
+                                        Join a session and stream the document
+  rusttalk seed [TICKET] [--name NAME]  Keep a session available without a window
+
+Options for every mode:
+  --blocklist-after N                   Ignore a neighbor after N of its messages failed to decode
+  --identity                            Use the stored identity instead of a throwaway one.
+                                        Peers cannot tell apart two processes sharing it,
+                                        so do not also run the desktop app with it
+
+In create and join mode, each line read from stdin is appended to the document.";
+
+const ONLINE_TIMEOUT: Duration = Duration::from_secs(10);
+const FLUSH_DELAY: Duration = Duration::from_secs(2);
+
+pub enum Command {
+    Create {
+        name: String,
+    },
+    Join {
+        name: String,
+        ticket: String,
+    },
+    Seed {
+        name: String,
+        ticket: Option<String>,
+    },
+}
+
+/// Parses the command line. `None` means no subcommand was given and the GUI should start.
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<(Command, SetupOptions)>> {
+    let mut name = None;
+    let mut options = SetupOptions::default();
+    let mut positional = Vec::new();
+    let mut stored_identity = false;
+
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => {
+                let Some(value) = args.next() else {
+                    bail!("--name needs a value\n\n{USAGE}");
+                };
+                name = Some(value);
+            }
+            "--join-timeout" => {
+                let Some(secs) = args.next().and_then(|value| value.parse().ok()) else {
+                    bail!("--join-timeout needs a number of seconds\n\n{USAGE}");
+                };
+                options.join_timeout = Duration::from_secs(secs);
+            }
+            "--blocklist-after" => {
+                let Some(limit) = args.next().and_then(|value| value.parse().ok()) else {
+                    bail!("--blocklist-after needs a number of messages\n\n{USAGE}");
+                };
+                options.blocklist_after = Some(limit);
+            }
+            "--identity" => stored_identity = true,
+            "-h" | "--help" | "help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match (positional.next().as_deref(), positional.next()) {
+        (None, _) => return Ok(None),
+        (Some("create"), None) => Command::Create {
+            name: name.unwrap_or_else(|| "rusttalk".to_string()),
+        },
+        (Some("join"), Some(ticket)) => Command::Join {
+            name: name.unwrap_or_else(|| "rusttalk".to_string()),
+            ticket,
+        },
+        (Some("seed"), ticket) => Command::Seed {
+            name: name.unwrap_or_else(|| "Seed".to_string()),
+            ticket,
+        },
+        _ => bail!("Unrecognized arguments\n\n{USAGE}"),
+    };
+
+    if positional.next().is_some() {
+        bail!("Too many arguments\n\n{USAGE}");
+    }
+
+    if stored_identity {
+        options.secret_key = Some(identity::load_or_create()?);
+    }
+
+    Ok(Some((command, options)))
+}
+
+pub async fn run(command: Command, options: SetupOptions) -> Result<()> {
+    let (name, start, is_seed) = match command {
+        Command::Create { name } => (name, SessionStart::Create, false),
+        Command::Join { name, ticket } => (name, SessionStart::Join { ticket }, false),
+        Command::Seed { name, ticket } => match ticket {
+            Some(ticket) => (name, SessionStart::Serve { ticket }, true),
+            None => (name, SessionStart::Create, true),
+        },
+    };
+
+    let session = Session::start_with(Profile::new(name), start, options).await?;
+
+    // Wait for a relay so the printed ticket is dialable from outside the local network
+    let _ = timeout(ONLINE_TIMEOUT, session.endpoint().online()).await;
+    eprintln!("Session ticket:");
+    println!("{}", session.ticket());
+
+    let result = if is_seed {
+        signal::ctrl_c().await.map_err(Into::into)
+    } else {
+        stream_document(&session).await
+    };
+
+    session.leave().await;
+
+    result
+}
+
+/// Prints the document whenever it changes and appends stdin lines to it, until
+/// Ctrl+C or the end of stdin.
+async fn stream_document(session: &Session) -> Result<()> {
+    let mut stdin_lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut events = session.subscribe();
+    let mut printed_version = VersionVector::new();
+    let mut flush_deadline = None;
+
+    print_if_changed(session, &mut printed_version);
+
+    loop {
+        select! {
+            _ = signal::ctrl_c() => return Ok(()),
+            line = stdin_lines.next_line(), if flush_deadline.is_none() => {
+                let Some(line) = line? else {
+                    // Give appended text a moment to reach peers before leaving
+                    flush_deadline = Some(Instant::now() + FLUSH_DELAY);
+                    continue;
+                };
+
+                session.append_text(MAIN_DOCUMENT, &format!("{line}\n"))?;
+                print_if_changed(session, &mut printed_version);
+            }
+            event = events.recv() => match event {
+                Ok(SessionEvent::DocumentChanged) | Err(RecvError::Lagged(_)) => {
+                    print_if_changed(session, &mut printed_version);
+                }
+                Ok(SessionEvent::StatusChanged) => {
+                    eprintln!("Connection status: {:?}", session.status());
+                }
+                Ok(SessionEvent::PeerLeft) => {
+                    if let Some(departed) = session.presence().recently_left.last() {
+                        eprintln!("{} left", departed.profile.name);
+                    }
+                }
+                Ok(SessionEvent::PresenceChanged | SessionEvent::MessageRejected) => {}
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = sleep_until(flush_deadline.unwrap_or_else(Instant::now)), if flush_deadline.is_some() => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn print_if_changed(session: &Session, printed_version: &mut VersionVector) {
+    let version = session.loro_doc().oplog_vv();
+    if version != *printed_version {
+        println!("--- document updated ---");
+        let text = workspace::document_text(session.loro_doc(), MAIN_DOCUMENT);
+        println!("{}", text.to_string());
+        *printed_version = version;
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use eframe::egui::{self, Color32, RichText, Ui};
+
+use rusttalk_core::workspace::{DocumentFormat, DocumentId, MAIN_DOCUMENT};
+
+use crate::{local_undo::LocalUndo, screen_session::peer_color, task_start_session::SessionState};
+
+const SIDEBAR_WIDTH: f32 = 200.0;
+
+/// Shows another document of the workspace in the editor.
+pub fn switch_document(state: &mut SessionState, id: DocumentId) {
+    state.document = id;
+    state.cursors = None;
+    state.viewport_top = None;
+    state.history.selected = None;
+    state.link_edit = None;
+    state.peer_caret_moves.clear();
+    // The undo stack only holds edits to the document we leave, so it starts over
+    state.local_undo = LocalUndo::new(state.session.loro_doc());
+    let _ = state.session.open_document(&state.document);
+}
+
+/// Lists the session's documents with who has each one open, and lets the user
+/// create, rename, delete and switch between them.
+pub fn render_document_sidebar(ui: &mut Ui, state: &mut SessionState) {
+    let documents = state.session.documents();
+
+    // Somebody deleted the document we were looking at
+    if !documents
+        .iter()
+        .any(|document| document.id == state.document)
+    {
+        switch_document(state, MAIN_DOCUMENT.to_string());
+    }
+
+    let hues = state.session.peer_hues();
+    let mut open_by: HashMap<DocumentId, Vec<(String, Color32)>> = HashMap::new();
+    for (awareness, _) in state.session.presence().awareness_cache.values() {
+        open_by
+            .entry(awareness.document.clone())
+            .or_default()
+            .push((
+                awareness.profile.name.clone(),
+                peer_color(&hues, &awareness.endpoint_id),
+            ));
+    }
+
+    ui.vertical(|ui| {
+        ui.set_width(SIDEBAR_WIDTH);
+
+        ui.horizontal(|ui| {
+            ui.label(
+                RichText::new("Documents")
+                    .size(14.0)
+                    .color(egui::Color32::from_rgb(71, 85, 105)),
+            );
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                let new_button = egui::Button::new(RichText::new("+").size(14.0))
+                    .min_size(egui::vec2(28.0, 28.0))
+                    .corner_radius(6);
+                if ui.add(new_button).on_hover_text("New document").clicked() {
+                    let title = format!("Untitled {}", documents.len() + 1);
+                    if let Ok(id) = state.session.create_document(&title) {
+                        switch_document(state, id.clone());
+                        state.renaming = Some((id, title));
+                    }
+                }
+            });
+        });
+
+        ui.add_space(8.0);
+
+        for document in &documents {
+            if let Some((id, title)) = &mut state.renaming
+                && *id == document.id
+            {
+                let response = ui.add(
+                    egui::TextEdit::singleline(title)
+                        .desired_width(SIDEBAR_WIDTH)
+                        .margin(egui::vec2(6.0, 4.0)),
+                );
+                response.request_focus();
+
+                if ui.input(|input| input.key_pressed(egui::Key::Escape)) {
+                    state.renaming = None;
+                } else if response.lost_focus() {
+                    let _ = state.session.rename_document(id, title.trim());
+                    state.renaming = None;
+                }
+                continue;
+            }
+
+            ui.horizontal(|ui| {
+                let selected = document.id == state.document;
+                let response = ui.selectable_label(
+                    selected,
+                    RichText::new(&document.title)
+                        .size(14.0)
+                        .color(if selected {
+                            egui::Color32::from_rgb(30, 41, 59)
+                        } else {
+                            egui::Color32::from_rgb(71, 85, 105)
+                        }),
+                );
+
+                if response.double_clicked() {
+                    state.renaming = Some((document.id.clone(), document.title.clone()));
+                } else if response.clicked() && !selected {
+                    switch_document(state, document.id.clone());
+                }
+
+                if document.format == DocumentFormat::Markdown {
+                    ui.label(
+                        RichText::new("M↓")
+                            .size(11.0)
+                            .color(egui::Color32::from_rgb(100, 116, 139)),
+                    )
+                    .on_hover_text("Markdown");
+                }
+
+                response.context_menu(|ui| {
+                    if ui.button("Rename").clicked() {
+                        state.renaming = Some((document.id.clone(), document.title.clone()));
+                        ui.close();
+                    }
+                    let delete_button =
+                        ui.add_enabled(document.id != MAIN_DOCUMENT, egui::Button::new("Delete"));
+                    if delete_button.clicked() {
+                        let _ = state.session.delete_document(&document.id);
+                        ui.close();
+                    }
+                });
+
+                // Who else has this document open
+                for (name, color) in open_by.get(&document.id).into_iter().flatten() {
+                    let (rect, response) =
+                        ui.allocate_exact_size(egui::vec2(10.0, 10.0), egui::Sense::hover());
+                    ui.painter().circle_filled(rect.center(), 4.0, *color);
+                    response.on_hover_text(name);
+                }
+            });
+        }
+    });
+}
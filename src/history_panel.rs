@@ -0,0 +1,366 @@
+use std::{
+    cmp::Reverse,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eframe::egui::{self, Color32, RichText, text::LayoutJob};
+use loro::{
+    ChangeMeta, ContainerTrait, Frontiers, ID, LoroDoc, PeerID, TextDelta, VersionVector,
+    event::Diff,
+};
+
+use rusttalk_core::{
+    Session, authors,
+    workspace::{self, DocumentId},
+};
+
+use crate::task_start_session::SessionState;
+
+#[derive(Default)]
+pub struct HistoryState {
+    pub open: bool,
+    pub selected: Option<HistoryPreview>,
+    /// Changes to the document in `listed_for` up to `listed_vv`, newest first.
+    /// Walking the whole oplog is too slow to repeat every frame while typing, so
+    /// only changes past `listed_vv` are added.
+    pub changes: Vec<ChangeMeta>,
+    pub listed_for: Option<DocumentId>,
+    pub listed_vv: VersionVector,
+}
+
+/// Read-only view of the document as it was right after a change.
+pub struct HistoryPreview {
+    pub change_id: ID,
+    pub frontiers: Frontiers,
+    /// The document's text at `frontiers`, which never changes once read.
+    pub past_text: String,
+    /// Document version the diff was computed against.
+    pub built_at: Frontiers,
+    pub diff: Vec<DiffSegment>,
+}
+
+pub enum DiffSegment {
+    Unchanged(String),
+    Inserted(String),
+    Deleted(String),
+}
+
+/// Adds the changes to the document's text made since `listed_vv` to `changes`,
+/// keeping them newest first.
+fn list_new_changes(
+    loro_doc: &LoroDoc,
+    document: &str,
+    listed_vv: &VersionVector,
+    changes: &mut Vec<ChangeMeta>,
+) {
+    let text_id = workspace::document_text(loro_doc, document).id();
+    let oplog_vv = loro_doc.oplog_vv();
+    if listed_vv.includes_vv(&oplog_vv) {
+        return;
+    }
+
+    for (peer, end) in oplog_vv.iter() {
+        let listed_end = listed_vv.get(peer).copied().unwrap_or(0);
+        let mut counter = listed_end;
+        while counter < *end {
+            let Some(change) = loro_doc.get_change(ID::new(*peer, counter)) else {
+                break;
+            };
+            counter = change.id.counter + change.len as i32;
+            if !loro_doc
+                .get_changed_containers_in(change.id, change.len)
+                .contains(&text_id)
+            {
+                continue;
+            }
+            // Quick successive edits grow the last change instead of starting a new one
+            if change.id.counter < listed_end {
+                changes.retain(|listed| listed.id != change.id);
+            }
+            changes.push(change);
+        }
+    }
+    // Concurrent changes can share a lamport time, so the id keeps the order stable
+    changes.sort_by_key(|change| Reverse((change.lamport, change.id)));
+}
+
+fn build_preview(loro_doc: &LoroDoc, document: &str, change: &ChangeMeta) -> HistoryPreview {
+    let last_op = ID::new(change.id.peer, change.id.counter + change.len as i32 - 1);
+    let frontiers = Frontiers::from_id(last_op);
+
+    // Read it from a fork so the live document keeps syncing and accepting edits
+    let past_doc = loro_doc.fork_at(&frontiers);
+    let past_text = workspace::document_text(&past_doc, document).to_string();
+
+    let mut preview = HistoryPreview {
+        change_id: change.id,
+        frontiers,
+        past_text,
+        built_at: Frontiers::default(),
+        diff: Vec::new(),
+    };
+    update_diff(loro_doc, document, &mut preview);
+
+    preview
+}
+
+/// Recomputes what changed between the previewed version and the current one.
+fn update_diff(loro_doc: &LoroDoc, document: &str, preview: &mut HistoryPreview) {
+    let current = loro_doc.oplog_frontiers();
+    let doc_text = workspace::document_text(loro_doc, document);
+    // Without a diff the old text is shown as it was
+    let deltas = loro_doc
+        .diff(&preview.frontiers, &current)
+        .ok()
+        .and_then(|diff| {
+            diff.iter().find_map(|(container_id, diff)| match diff {
+                Diff::Text(deltas) if *container_id == doc_text.id() => Some(deltas.clone()),
+                _ => None,
+            })
+        })
+        .unwrap_or_default();
+
+    // Walk the deltas over the old text to lay out what changed since then
+    let mut old_chars = preview.past_text.chars();
+    let mut segments = Vec::new();
+    for delta in deltas {
+        match delta {
+            TextDelta::Retain { retain, .. } => {
+                segments.push(DiffSegment::Unchanged(
+                    old_chars.by_ref().take(retain).collect(),
+                ));
+            }
+            TextDelta::Delete { delete } => {
+                segments.push(DiffSegment::Deleted(
+                    old_chars.by_ref().take(delete).collect(),
+                ));
+            }
+            TextDelta::Insert { insert, .. } => segments.push(DiffSegment::Inserted(insert)),
+        }
+    }
+    segments.push(DiffSegment::Unchanged(old_chars.collect()));
+
+    preview.diff = segments;
+    preview.built_at = current;
+}
+
+/// Name recorded for the peer's changes, or for peers on builds that did not record
+/// one, the name it currently shows in awareness.
+fn author_name(session: &Session, peer: PeerID) -> String {
+    if let Some(author) = authors::author(session.loro_doc(), peer) {
+        return author.name;
+    }
+
+    session
+        .presence()
+        .awareness_cache
+        .values()
+        .find(|(awareness, _)| awareness.loro_peer_id == peer)
+        .map(|(awareness, _)| awareness.profile.name.clone())
+        .unwrap_or_else(|| "Unknown peer".to_string())
+}
+
+fn format_age(timestamp_secs: i64) -> String {
+    if timestamp_secs == 0 {
+        return "unknown time".to_string();
+    }
+
+    let now_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let age = (now_secs - timestamp_secs).max(0);
+
+    match age {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", age / 60),
+        3600..86400 => format!("{} h ago", age / 3600),
+        _ => format!("{} days ago", age / 86400),
+    }
+}
+
+fn diff_layout_job(segments: &[DiffSegment]) -> LayoutJob {
+    let font_id = egui::FontId::new(14.0, egui::FontFamily::Proportional);
+    let mut job = LayoutJob::default();
+
+    for segment in segments {
+        let (text, format) = match segment {
+            DiffSegment::Unchanged(text) => (
+                text,
+                egui::TextFormat::simple(font_id.clone(), Color32::from_rgb(71, 85, 105)),
+            ),
+            DiffSegment::Inserted(text) => (
+                text,
+                egui::TextFormat {
+                    background: Color32::from_rgb(220, 252, 231),
+                    ..egui::TextFormat::simple(font_id.clone(), Color32::from_rgb(22, 101, 52))
+                },
+            ),
+            DiffSegment::Deleted(text) => (
+                text,
+                egui::TextFormat {
+                    background: Color32::from_rgb(254, 226, 226),
+                    strikethrough: egui::Stroke::new(1.0, Color32::from_rgb(153, 27, 27)),
+                    ..egui::TextFormat::simple(font_id.clone(), Color32::from_rgb(153, 27, 27))
+                },
+            ),
+        };
+        job.append(text, 0.0, format);
+    }
+
+    job
+}
+
+pub fn render_history_window(ctx: &egui::Context, state: &mut SessionState) {
+    let mut open = state.history.open;
+
+    egui::Window::new("History")
+        .open(&mut open)
+        .default_size(egui::vec2(480.0, 520.0))
+        .show(ctx, |ui| {
+            let loro_doc = state.session.loro_doc().clone();
+            let frontiers = loro_doc.oplog_frontiers();
+
+            let history = &mut state.history;
+            if history.listed_for.as_ref() != Some(&state.document) {
+                history.changes.clear();
+                history.listed_vv = VersionVector::new();
+                history.listed_for = Some(state.document.clone());
+            }
+            list_new_changes(
+                &loro_doc,
+                &state.document,
+                &history.listed_vv,
+                &mut history.changes,
+            );
+            history.listed_vv = loro_doc.oplog_vv();
+
+            egui::ScrollArea::vertical()
+                .id_salt("history_changes")
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    for change in &state.history.changes {
+                        let author = author_name(&state.session, change.id.peer);
+                        let label = format!(
+                            "{author} · {} · {} ops",
+                            format_age(change.timestamp),
+                            change.len
+                        );
+
+                        let is_selected = state
+                            .history
+                            .selected
+                            .as_ref()
+                            .is_some_and(|preview| preview.change_id == change.id);
+                        if ui.selectable_label(is_selected, label).clicked() {
+                            state.history.selected =
+                                Some(build_preview(&loro_doc, &state.document, change));
+                        }
+                    }
+                });
+
+            // Keep the diff current as local and remote edits arrive
+            if let Some(preview) = &mut state.history.selected
+                && preview.built_at != frontiers
+            {
+                update_diff(&loro_doc, &state.document, preview);
+            }
+
+            let Some(preview) = &state.history.selected else {
+                ui.label(
+                    RichText::new("Select a change to preview that version")
+                        .size(12.0)
+                        .color(Color32::from_rgb(100, 116, 139)),
+                );
+                return;
+            };
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label(
+                    RichText::new("Changes since this version")
+                        .size(14.0)
+                        .color(Color32::from_rgb(71, 85, 105)),
+                );
+
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let restore_button =
+                        egui::Button::new(RichText::new("Restore this version").size(12.0))
+                            .min_size(egui::vec2(80.0, 28.0))
+                            .corner_radius(6);
+
+                    // Restoring is a new forward edit, so it reaches every peer as usual
+                    if ui.add(restore_button).clicked()
+                        && state
+                            .session
+                            .restore_document(&state.document, &preview.frontiers)
+                            .is_ok()
+                    {
+                        state.egui_cursors_needs_update = true;
+                    }
+                });
+            });
+
+            egui::ScrollArea::vertical()
+                .id_salt("history_preview")
+                .show(ui, |ui| {
+                    ui.label(diff_layout_job(&preview.diff));
+                });
+        });
+
+    state.history.open = open;
+}
+
+#[cfg(test)]
+mod tests {
+    use rusttalk_core::workspace::MAIN_DOCUMENT;
+
+    use super::*;
+
+    fn edit(loro_doc: &LoroDoc, document: &str, text: &str) {
+        let doc_text = workspace::document_text(loro_doc, document);
+        doc_text.insert(doc_text.len_unicode(), text).unwrap();
+        loro_doc.commit();
+    }
+
+    fn listed(changes: &[ChangeMeta]) -> Vec<(ID, usize)> {
+        changes
+            .iter()
+            .map(|change| (change.id, change.len))
+            .collect()
+    }
+
+    #[test]
+    fn listing_new_changes_matches_a_full_walk() {
+        let loro_doc = LoroDoc::new();
+        loro_doc.set_change_merge_interval(60);
+        let remote = LoroDoc::new();
+        remote.set_peer_id(2).unwrap();
+
+        let mut changes = Vec::new();
+        let mut listed_vv = VersionVector::new();
+        let steps: [&dyn Fn(); 4] = [
+            &|| edit(&loro_doc, MAIN_DOCUMENT, "Hello"),
+            // Merged into the change before
+            &|| edit(&loro_doc, MAIN_DOCUMENT, " world"),
+            &|| edit(&loro_doc, "notes", "elsewhere"),
+            &|| {
+                edit(&remote, MAIN_DOCUMENT, "!");
+                loro_doc
+                    .import(&remote.export(loro::ExportMode::all_updates()).unwrap())
+                    .unwrap();
+            },
+        ];
+        for step in steps {
+            step();
+            list_new_changes(&loro_doc, MAIN_DOCUMENT, &listed_vv, &mut changes);
+            listed_vv = loro_doc.oplog_vv();
+
+            let mut all = Vec::new();
+            list_new_changes(&loro_doc, MAIN_DOCUMENT, &VersionVector::new(), &mut all);
+            assert_eq!(listed(&changes), listed(&all));
+        }
+        assert_eq!(changes.len(), 2);
+    }
+}
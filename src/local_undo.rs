@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use loro::{LoroDoc, UndoItemMeta, UndoManager};
+use parking_lot::Mutex;
+
+use rusttalk_core::{awareness::LoroCursors, workspace};
+
+/// Typing within this window is merged into a single undo step.
+const UNDO_MERGE_INTERVAL_MS: i64 = 1000;
+
+/// Undo/redo over the local user's own edits to the open document, so remote edits
+/// and workspace changes such as renames are never reverted. Each undo step
+/// remembers the selection it was made from.
+pub struct LocalUndo {
+    undo_manager: UndoManager,
+    selection: Arc<Mutex<LoroCursors>>,
+}
+
+impl LocalUndo {
+    pub fn new(loro_doc: &LoroDoc) -> Self {
+        let mut undo_manager = UndoManager::new(loro_doc);
+        undo_manager.set_merge_interval(UNDO_MERGE_INTERVAL_MS);
+        undo_manager.add_exclude_origin_prefix(workspace::WORKSPACE_ORIGIN);
+
+        // The selection slot is written before each local commit and read back when
+        // an item is popped, carrying the selection through the undo stack
+        let selection: Arc<Mutex<LoroCursors>> = Arc::new(Mutex::new(None));
+        undo_manager.set_on_push(Some(Box::new({
+            let selection = selection.clone();
+            move |_, _, _| {
+                let mut meta = UndoItemMeta::new();
+                if let Some((primary, secondary)) = &*selection.lock() {
+                    meta.add_cursor(primary);
+                    meta.add_cursor(secondary);
+                }
+                meta
+            }
+        })));
+        undo_manager.set_on_pop(Some(Box::new({
+            let selection = selection.clone();
+            move |_, _, meta| {
+                if let [primary, secondary] = meta.cursors.as_slice() {
+                    *selection.lock() = Some((primary.cursor.clone(), secondary.cursor.clone()));
+                }
+            }
+        })));
+
+        Self {
+            undo_manager,
+            selection,
+        }
+    }
+
+    /// Remembers the selection the next local change is made from.
+    pub fn record_selection(&self, cursors: &LoroCursors) {
+        *self.selection.lock() = cursors.clone();
+    }
+
+    /// Undoes the last local step, returning the selection to restore.
+    pub fn undo(&mut self) -> Option<LoroCursors> {
+        match self.undo_manager.undo() {
+            Ok(true) => Some(self.selection.lock().clone()),
+            _ => None,
+        }
+    }
+
+    /// Redoes the last undone local step, returning the selection to restore.
+    pub fn redo(&mut self) -> Option<LoroCursors> {
+        match self.undo_manager.redo() {
+            Ok(true) => Some(self.selection.lock().clone()),
+            _ => None,
+        }
+    }
+}
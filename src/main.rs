@@ -3,17 +3,26 @@ use std::sync::Arc;
 use eframe::egui;
 use parking_lot::Mutex;
 
-mod awareness;
-mod gossip_message;
+mod cli;
+mod document_sidebar;
+mod history_panel;
+mod local_undo;
+mod markdown;
+mod markdown_view;
+mod rich_text_format;
+mod screen_loading;
 mod screen_lobby;
 mod screen_session;
 mod task_leave_session;
 mod task_start_session;
 
+use screen_loading::render_loading;
 use screen_lobby::render_lobby;
 use screen_session::render_session;
 
-use crate::{screen_lobby::LobbyState, task_start_session::SessionState};
+use crate::{
+    screen_loading::LoadingState, screen_lobby::LobbyState, task_start_session::SessionState,
+};
 
 fn setup_custom_style(ctx: &egui::Context) {
     ctx.set_theme(egui::Theme::Light);
@@ -38,6 +47,31 @@ fn setup_custom_style(ctx: &egui::Context) {
     style.spacing.item_spacing = egui::vec2(12.0, 16.0);
 
     ctx.set_style(style);
+
+    // Bold faces, with the default fonts behind them for any glyphs they lack
+    let mut fonts = egui::FontDefinitions::default();
+    for (name, data, base) in [
+        (
+            rich_text_format::BOLD_FAMILY,
+            include_bytes!("../assets/fonts/DejaVuSans-Bold.ttf").as_slice(),
+            egui::FontFamily::Proportional,
+        ),
+        (
+            rich_text_format::BOLD_MONOSPACE_FAMILY,
+            include_bytes!("../assets/fonts/DejaVuSansMono-Bold.ttf").as_slice(),
+            egui::FontFamily::Monospace,
+        ),
+    ] {
+        fonts
+            .font_data
+            .insert(name.to_owned(), Arc::new(egui::FontData::from_static(data)));
+        let mut family = vec![name.to_owned()];
+        family.extend(fonts.families[&base].iter().cloned());
+        fonts
+            .families
+            .insert(egui::FontFamily::Name(name.into()), family);
+    }
+    ctx.set_fonts(fonts);
 }
 
 #[derive(Clone)]
@@ -58,12 +92,27 @@ impl App {
 
 pub enum State {
     Lobby(LobbyState),
-    Loading,
-    Session(SessionState),
+    Loading(LoadingState),
+    Session(Box<SessionState>),
 }
 
 #[tokio::main]
 async fn main() -> eframe::Result {
+    match cli::parse_args(std::env::args()) {
+        Ok(None) => {}
+        Ok(Some((command, options))) => {
+            if let Err(err) = cli::run(command, options).await {
+                eprintln!("{err:#}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Err(err) => {
+            eprintln!("{err:#}");
+            std::process::exit(2);
+        }
+    }
+
     tokio::task::block_in_place(|| {
         let options = eframe::NativeOptions {
             viewport: egui::ViewportBuilder::default().with_inner_size([800.0, 700.0]),
@@ -77,11 +126,7 @@ async fn main() -> eframe::Result {
                 setup_custom_style(&cc.egui_ctx);
 
                 let app = App {
-                    state: Arc::new(Mutex::new(State::Lobby(LobbyState {
-                        join_existing: false,
-                        name_input: String::new(),
-                        existing_peer_input: String::new(),
-                    }))),
+                    state: Arc::new(Mutex::new(State::Lobby(LobbyState::new()))),
                     egui_ctx: cc.egui_ctx.clone(),
                 };
                 Ok(Box::new(app))
@@ -109,9 +154,7 @@ fn render_ui(ui: &mut egui::Ui, app: &mut App) {
 
     match &mut *state {
         State::Lobby(state) => render_lobby(ui, app.clone(), state),
-        State::Loading => {
-            ui.spinner();
-        }
+        State::Loading(state) => render_loading(ui, state),
         State::Session(state) => render_session(ui, app.clone(), state),
     }
 }
@@ -0,0 +1,1090 @@
+use std::ops::Range;
+
+/// Styling a piece of Markdown text gets from the syntax around it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InlineStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub strikethrough: bool,
+    pub code: bool,
+    pub link: Option<String>,
+}
+
+/// A piece of a line in byte positions, either text or the syntax styling it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlinePiece {
+    pub range: Range<usize>,
+    pub style: InlineStyle,
+    pub markup: bool,
+}
+
+/// Rendered text with the syntax taken out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineSpan {
+    pub text: String,
+    pub style: InlineStyle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListMarker {
+    Bullet,
+    Number(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListItem {
+    /// Nesting level, from the indentation of the marker.
+    pub depth: usize,
+    pub marker: ListMarker,
+    /// Set for task items, `- [ ]` and `- [x]`.
+    pub checked: Option<bool>,
+    pub content: Vec<InlineSpan>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Alignment {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub alignments: Vec<Alignment>,
+    pub header: Vec<Vec<InlineSpan>>,
+    pub rows: Vec<Vec<Vec<InlineSpan>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Heading { level: u8, content: Vec<InlineSpan> },
+    Paragraph(Vec<InlineSpan>),
+    Quote(Vec<InlineSpan>),
+    List(Vec<ListItem>),
+    Code { language: String, code: String },
+    Table(Table),
+    Rule,
+}
+
+/// What a stretch of the source is, for highlighting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Text,
+    /// Syntax characters such as `#`, `**`, list markers, fences and link targets.
+    Markup,
+    Heading(u8),
+    Quote,
+    CodeBlock,
+}
+
+/// A run of the source in byte positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub range: Range<usize>,
+    pub kind: SourceKind,
+    pub style: InlineStyle,
+}
+
+/// How a single line starts, in byte offsets within the line.
+enum LineKind<'a> {
+    Blank,
+    Fence {
+        marker: &'a str,
+        info: &'a str,
+    },
+    Heading {
+        level: u8,
+        content_start: usize,
+    },
+    Rule,
+    Quote {
+        content_start: usize,
+    },
+    ListItem {
+        indent: usize,
+        marker: Range<usize>,
+        number: Option<u64>,
+        content_start: usize,
+    },
+    Text,
+}
+
+fn classify(line: &str) -> LineKind<'_> {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() {
+        return LineKind::Blank;
+    }
+    let indent = line.len() - trimmed.len();
+
+    for fence_char in ['`', '~'] {
+        let fence_len = trimmed.len() - trimmed.trim_start_matches(fence_char).len();
+        if fence_len >= 3 {
+            return LineKind::Fence {
+                marker: &trimmed[..fence_len],
+                info: trimmed[fence_len..].trim(),
+            };
+        }
+    }
+
+    let hashes = trimmed.len() - trimmed.trim_start_matches('#').len();
+    if (1..=6).contains(&hashes) && (trimmed.len() == hashes || trimmed[hashes..].starts_with(' '))
+    {
+        let content = &trimmed[hashes..];
+        return LineKind::Heading {
+            level: hashes as u8,
+            content_start: line.len() - content.trim_start().len(),
+        };
+    }
+
+    if is_rule(trimmed) {
+        return LineKind::Rule;
+    }
+
+    if let Some(content) = trimmed.strip_prefix('>') {
+        let content = content.strip_prefix(' ').unwrap_or(content);
+        return LineKind::Quote {
+            content_start: line.len() - content.len(),
+        };
+    }
+
+    if let Some(rest) = trimmed
+        .strip_prefix(['-', '*', '+'])
+        .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+    {
+        return LineKind::ListItem {
+            indent,
+            marker: indent..indent + 1,
+            number: None,
+            content_start: line.len() - rest.trim_start().len(),
+        };
+    }
+
+    let digits = trimmed.len()
+        - trimmed
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    if (1..=9).contains(&digits)
+        && let Some(rest) = trimmed[digits..]
+            .strip_prefix(['.', ')'])
+            .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+    {
+        return LineKind::ListItem {
+            indent,
+            marker: indent..indent + digits + 1,
+            number: trimmed[..digits].parse().ok(),
+            content_start: line.len() - rest.trim_start().len(),
+        };
+    }
+
+    LineKind::Text
+}
+
+/// Three or more of the same `-`, `*` or `_`, optionally spaced out.
+fn is_rule(trimmed: &str) -> bool {
+    let Some(first) = trimmed
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '-' | '*' | '_'))
+    else {
+        return false;
+    };
+    trimmed.chars().all(|c| c == first || c == ' ')
+        && trimmed.chars().filter(|c| *c == first).count() >= 3
+}
+
+fn closes_fence(line: &str, marker: &str) -> bool {
+    let trimmed = line.trim();
+    let fence_char = marker.chars().next().unwrap_or('`');
+    trimmed.len() >= marker.len() && trimmed.chars().all(|c| c == fence_char)
+}
+
+/// Task box at the start of a list item's content, with the rest of the content.
+fn task_box(content: &str) -> Option<(bool, &str)> {
+    let checked = match content.get(..3)? {
+        "[ ]" => false,
+        "[x]" | "[X]" => true,
+        _ => return None,
+    };
+    let rest = &content[3..];
+    (rest.is_empty() || rest.starts_with(' ')).then(|| (checked, rest.trim_start()))
+}
+
+/// The row under a table's header, like `| --- | :---: |`.
+fn is_table_delimiter(line: &str) -> bool {
+    let cells = table_cells(line);
+    line.contains('-')
+        && !cells.is_empty()
+        && cells.iter().all(|cell| {
+            let cell = line[cell.clone()].trim();
+            !cell.is_empty() && cell.chars().all(|c| matches!(c, '-' | ':'))
+        })
+}
+
+fn starts_table(lines: &[&str], index: usize) -> bool {
+    lines[index].contains('|')
+        && lines
+            .get(index + 1)
+            .is_some_and(|next| is_table_delimiter(next))
+}
+
+/// Byte ranges of the cells of a table row, between unescaped pipes.
+fn table_cells(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let mut pipes = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'|' => pipes.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let mut bounds = Vec::new();
+    let start = line.len() - line.trim_start().len();
+    let end = line.trim_end().len();
+    if pipes.first() != Some(&start) {
+        bounds.push(start);
+    }
+    bounds.extend(pipes.iter().map(|pipe| pipe + 1));
+
+    let mut cells = Vec::new();
+    for (index, cell_start) in bounds.iter().enumerate() {
+        let cell_end = pipes
+            .iter()
+            .copied()
+            .find(|pipe| pipe >= cell_start)
+            .unwrap_or(end);
+        // Nothing after a closing pipe
+        if *cell_start >= end && index > 0 {
+            break;
+        }
+        cells.push(*cell_start..cell_end);
+    }
+    cells
+}
+
+fn alignment(cell: &str) -> Alignment {
+    let cell = cell.trim();
+    match (cell.starts_with(':'), cell.ends_with(':')) {
+        (true, true) => Alignment::Center,
+        (false, true) => Alignment::Right,
+        _ => Alignment::Left,
+    }
+}
+
+/// Splits a line's inline syntax from its text. Emphasis only opens when it is
+/// closed later on the same line, so stray `*` and `_` stay literal.
+pub fn parse_inline(text: &str) -> Vec<InlinePiece> {
+    let mut pieces = Vec::new();
+    parse_inline_into(text, 0..text.len(), InlineStyle::default(), &mut pieces);
+    pieces
+}
+
+fn push_piece(
+    pieces: &mut Vec<InlinePiece>,
+    range: Range<usize>,
+    style: &InlineStyle,
+    markup: bool,
+) {
+    if range.is_empty() {
+        return;
+    }
+    if let Some(last) = pieces.last_mut()
+        && last.range.end == range.start
+        && last.markup == markup
+        && last.style == *style
+    {
+        last.range.end = range.end;
+        return;
+    }
+    pieces.push(InlinePiece {
+        range,
+        style: style.clone(),
+        markup,
+    });
+}
+
+fn parse_inline_into(
+    text: &str,
+    range: Range<usize>,
+    base: InlineStyle,
+    pieces: &mut Vec<InlinePiece>,
+) {
+    let bytes = text.as_bytes();
+    let end = range.end;
+    let mut style = base;
+    let mut i = range.start;
+    let mut text_start = i;
+
+    let is_word = |at: usize| bytes.get(at).is_some_and(|b| b.is_ascii_alphanumeric());
+
+    while i < end {
+        match bytes[i] {
+            b'\\' if i + 1 < end && bytes[i + 1].is_ascii_punctuation() => {
+                push_piece(pieces, text_start..i, &style, false);
+                push_piece(pieces, i..i + 1, &style, true);
+                push_piece(pieces, i + 1..i + 2, &style, false);
+                i += 2;
+                text_start = i;
+            }
+            b'`' => {
+                let run = run_length(bytes, i, end);
+                let Some(close) = find_run(bytes, i + run, end, b'`', run) else {
+                    i += run;
+                    continue;
+                };
+                push_piece(pieces, text_start..i, &style, false);
+                push_piece(pieces, i..i + run, &style, true);
+                let code_style = InlineStyle {
+                    code: true,
+                    ..style.clone()
+                };
+                push_piece(pieces, i + run..close, &code_style, false);
+                push_piece(pieces, close..close + run, &style, true);
+                i = close + run;
+                text_start = i;
+            }
+            b'[' => {
+                let Some((label_end, url_range)) = link_at(bytes, i, end) else {
+                    i += 1;
+                    continue;
+                };
+                push_piece(pieces, text_start..i, &style, false);
+                push_piece(pieces, i..i + 1, &style, true);
+                let url = text[url_range.clone()]
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                let link_style = InlineStyle {
+                    link: Some(url),
+                    ..style.clone()
+                };
+                parse_inline_into(text, i + 1..label_end, link_style, pieces);
+                push_piece(pieces, label_end..url_range.end + 1, &style, true);
+                i = url_range.end + 1;
+                text_start = i;
+            }
+            b'h' if style.link.is_none()
+                && !is_word(i.wrapping_sub(1))
+                && (text[i..end].starts_with("https://")
+                    || text[i..end].starts_with("http://")) =>
+            {
+                let url_end = bare_url_end(text, i, end);
+                push_piece(pieces, text_start..i, &style, false);
+                let link_style = InlineStyle {
+                    link: Some(text[i..url_end].to_string()),
+                    ..style.clone()
+                };
+                push_piece(pieces, i..url_end, &link_style, false);
+                i = url_end;
+                text_start = i;
+            }
+            b'~' if run_length(bytes, i, end) == 2 => {
+                let toggles = style.strikethrough || find_run(bytes, i + 2, end, b'~', 2).is_some();
+                if toggles {
+                    push_piece(pieces, text_start..i, &style, false);
+                    push_piece(pieces, i..i + 2, &style, true);
+                    style.strikethrough = !style.strikethrough;
+                    text_start = i + 2;
+                }
+                i += 2;
+            }
+            delimiter @ (b'*' | b'_') => {
+                let run = run_length(bytes, i, end);
+                let len = run.min(2);
+                // Underscores inside words, as in snake_case, are not emphasis
+                if delimiter == b'_' && is_word(i.wrapping_sub(1)) && is_word(i + run) {
+                    i += run;
+                    continue;
+                }
+
+                let open = if len == 2 { style.bold } else { style.italic };
+                let toggles = open
+                    || (bytes.get(i + len).is_some_and(|b| !b.is_ascii_whitespace())
+                        && find_closer(bytes, i + len, end, delimiter, len).is_some());
+                if toggles {
+                    push_piece(pieces, text_start..i, &style, false);
+                    push_piece(pieces, i..i + len, &style, true);
+                    if len == 2 {
+                        style.bold = !style.bold;
+                    } else {
+                        style.italic = !style.italic;
+                    }
+                    text_start = i + len;
+                }
+                i += len;
+            }
+            _ => i += 1,
+        }
+    }
+
+    push_piece(pieces, text_start..end, &style, false);
+}
+
+fn run_length(bytes: &[u8], start: usize, end: usize) -> usize {
+    bytes[start..end]
+        .iter()
+        .take_while(|b| **b == bytes[start])
+        .count()
+}
+
+/// Start of the next run of exactly `len` copies of `byte`.
+fn find_run(bytes: &[u8], from: usize, end: usize, byte: u8, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < end {
+        if bytes[i] == byte {
+            let run = run_length(bytes, i, end);
+            if run == len {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Start of a closing emphasis delimiter, which has to follow non-whitespace.
+fn find_closer(bytes: &[u8], from: usize, end: usize, byte: u8, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < end {
+        if bytes[i] == byte {
+            let run = run_length(bytes, i, end);
+            if run >= len && !bytes[i - 1].is_ascii_whitespace() {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// For `[label](url)` starting at `start`, the end of the label and the range of
+/// what is between the parentheses.
+fn link_at(bytes: &[u8], start: usize, end: usize) -> Option<(usize, Range<usize>)> {
+    let mut depth = 0;
+    let mut i = start;
+    let label_end = loop {
+        if i >= end {
+            return None;
+        }
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    break i;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    };
+
+    if label_end + 1 >= end || bytes[label_end + 1] != b'(' {
+        return None;
+    }
+    let url_start = label_end + 2;
+    let url_end = url_start + bytes[url_start..end].iter().position(|b| *b == b')')?;
+
+    Some((label_end, url_start..url_end))
+}
+
+/// End of a bare URL, leaving out punctuation that usually ends the sentence.
+fn bare_url_end(text: &str, start: usize, end: usize) -> usize {
+    let url = &text[start..end];
+    let url = &url[..url.find(char::is_whitespace).unwrap_or(url.len())];
+    let url = url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '"']);
+    start + url.len()
+}
+
+/// The text of a line without its syntax, ready to render.
+pub fn inline_spans(text: &str) -> Vec<InlineSpan> {
+    let mut spans: Vec<InlineSpan> = Vec::new();
+    for piece in parse_inline(text) {
+        if piece.markup {
+            continue;
+        }
+        let piece_text = &text[piece.range];
+        match spans.last_mut() {
+            Some(last) if last.style == piece.style => last.text.push_str(piece_text),
+            _ => spans.push(InlineSpan {
+                text: piece_text.to_string(),
+                style: piece.style,
+            }),
+        }
+    }
+    spans
+}
+
+/// Parses the block structure of a Markdown document: headings, paragraphs, quotes,
+/// lists, fenced code, tables and rules. Lines of a paragraph are joined as in
+/// CommonMark, so a single line break does not start a new paragraph.
+pub fn parse_blocks(source: &str) -> Vec<Block> {
+    let lines = source.lines().collect::<Vec<_>>();
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+
+    let flush_paragraph = |paragraph: &mut Vec<&str>, blocks: &mut Vec<Block>| {
+        if !paragraph.is_empty() {
+            blocks.push(Block::Paragraph(inline_spans(&paragraph.join(" "))));
+            paragraph.clear();
+        }
+    };
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        match classify(line) {
+            LineKind::Blank => {
+                flush_paragraph(&mut paragraph, &mut blocks);
+                i += 1;
+            }
+            LineKind::Fence { marker, info } => {
+                flush_paragraph(&mut paragraph, &mut blocks);
+                let mut code = Vec::new();
+                i += 1;
+                while i < lines.len() && !closes_fence(lines[i], marker) {
+                    code.push(lines[i]);
+                    i += 1;
+                }
+                // Skip the closing fence, an unclosed block runs to the end
+                i += 1;
+                blocks.push(Block::Code {
+                    language: info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    code: code.join("\n"),
+                });
+            }
+            LineKind::Heading {
+                level,
+                content_start,
+            } => {
+                flush_paragraph(&mut paragraph, &mut blocks);
+                blocks.push(Block::Heading {
+                    level,
+                    content: inline_spans(line[content_start..].trim_end()),
+                });
+                i += 1;
+            }
+            LineKind::Rule => {
+                flush_paragraph(&mut paragraph, &mut blocks);
+                blocks.push(Block::Rule);
+                i += 1;
+            }
+            LineKind::Quote { .. } => {
+                flush_paragraph(&mut paragraph, &mut blocks);
+                let mut quote = Vec::new();
+                while let Some(LineKind::Quote { content_start }) =
+                    lines.get(i).map(|l| classify(l))
+                {
+                    quote.push(lines[i][content_start..].trim());
+                    i += 1;
+                }
+                blocks.push(Block::Quote(inline_spans(&quote.join(" "))));
+            }
+            LineKind::ListItem { .. } => {
+                flush_paragraph(&mut paragraph, &mut blocks);
+                let mut items: Vec<ListItem> = Vec::new();
+                let mut contents: Vec<String> = Vec::new();
+                while let Some(line) = lines.get(i) {
+                    match classify(line) {
+                        LineKind::ListItem {
+                            indent,
+                            number,
+                            content_start,
+                            ..
+                        } => {
+                            let content = line[content_start..].trim_end();
+                            let (checked, content) = match task_box(content) {
+                                Some((checked, rest)) => (Some(checked), rest),
+                                None => (None, content),
+                            };
+                            items.push(ListItem {
+                                depth: indent / 2,
+                                marker: number.map_or(ListMarker::Bullet, ListMarker::Number),
+                                checked,
+                                content: Vec::new(),
+                            });
+                            contents.push(content.to_string());
+                        }
+                        // Indented lines continue the item above
+                        LineKind::Text if line.starts_with([' ', '\t']) => {
+                            if let Some(content) = contents.last_mut() {
+                                content.push(' ');
+                                content.push_str(line.trim());
+                            }
+                        }
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                for (item, content) in items.iter_mut().zip(&contents) {
+                    item.content = inline_spans(content);
+                }
+                blocks.push(Block::List(items));
+            }
+            LineKind::Text if paragraph.is_empty() && starts_table(&lines, i) => {
+                let header = table_cells(line);
+                let delimiter = lines[i + 1];
+                let mut table = Table {
+                    alignments: table_cells(delimiter)
+                        .into_iter()
+                        .map(|cell| alignment(&delimiter[cell]))
+                        .collect(),
+                    header: header
+                        .into_iter()
+                        .map(|cell| inline_spans(line[cell].trim()))
+                        .collect(),
+                    rows: Vec::new(),
+                };
+                i += 2;
+                while let Some(row) = lines.get(i).filter(|row| row.contains('|')) {
+                    table.rows.push(
+                        table_cells(row)
+                            .into_iter()
+                            .map(|cell| inline_spans(row[cell].trim()))
+                            .collect(),
+                    );
+                    i += 1;
+                }
+                blocks.push(Block::Table(table));
+            }
+            LineKind::Text => {
+                paragraph.push(line.trim());
+                i += 1;
+            }
+        }
+    }
+    flush_paragraph(&mut paragraph, &mut blocks);
+
+    blocks
+}
+
+/// Splits the source into runs for syntax highlighting. The runs are in order
+/// and cover the whole source, so they can be laid out one after another.
+pub fn highlight(source: &str) -> Vec<SourceSpan> {
+    let lines = source.split_inclusive('\n').collect::<Vec<_>>();
+    let content_lines = lines
+        .iter()
+        .map(|line| line.trim_end_matches(['\n', '\r']))
+        .collect::<Vec<_>>();
+
+    let mut highlighter = Highlighter::default();
+    let mut open_fence: Option<&str> = None;
+    // Rows seen of the table the current line belongs to, the header being row 0
+    let mut table_row: Option<usize> = None;
+    let mut offset = 0;
+    for (index, line) in content_lines.iter().enumerate() {
+        let line_end = offset + line.len();
+
+        if let Some(marker) = open_fence {
+            if closes_fence(line, marker) {
+                open_fence = None;
+                highlighter.markup(offset..line_end);
+            } else {
+                let code = InlineStyle {
+                    code: true,
+                    ..Default::default()
+                };
+                highlighter.push(offset..line_end, SourceKind::CodeBlock, code);
+            }
+        } else {
+            let kind = classify(line);
+            table_row = match (&kind, table_row) {
+                (LineKind::Text, Some(row)) if line.contains('|') => Some(row + 1),
+                (LineKind::Text, _) if starts_table(&content_lines, index) => Some(0),
+                _ => None,
+            };
+
+            match kind {
+                LineKind::Blank => highlighter.text(offset..line_end),
+                LineKind::Fence { marker, .. } => {
+                    open_fence = Some(marker);
+                    highlighter.markup(offset..line_end);
+                }
+                LineKind::Heading {
+                    level,
+                    content_start,
+                } => {
+                    highlighter.markup(offset..offset + content_start);
+                    highlighter.inline(
+                        source,
+                        offset + content_start..line_end,
+                        SourceKind::Heading(level),
+                        InlineStyle::default(),
+                    );
+                }
+                LineKind::Rule => highlighter.markup(offset..line_end),
+                LineKind::Quote { content_start } => {
+                    highlighter.markup(offset..offset + content_start);
+                    highlighter.inline(
+                        source,
+                        offset + content_start..line_end,
+                        SourceKind::Quote,
+                        InlineStyle::default(),
+                    );
+                }
+                LineKind::ListItem {
+                    marker,
+                    content_start,
+                    ..
+                } => {
+                    let task_len = if task_box(&line[content_start..]).is_some() {
+                        3
+                    } else {
+                        0
+                    };
+                    highlighter.text(offset..offset + marker.start);
+                    highlighter.markup(offset + marker.start..offset + content_start + task_len);
+                    highlighter.inline(
+                        source,
+                        offset + content_start + task_len..line_end,
+                        SourceKind::Text,
+                        InlineStyle::default(),
+                    );
+                }
+                // The delimiter row under the header is all syntax
+                LineKind::Text if table_row == Some(1) => highlighter.markup(offset..line_end),
+                LineKind::Text if table_row.is_some() => {
+                    let cell_style = InlineStyle {
+                        bold: table_row == Some(0),
+                        ..Default::default()
+                    };
+                    let mut pos = offset;
+                    for cell in table_cells(line) {
+                        highlighter.markup(pos..offset + cell.start);
+                        highlighter.inline(
+                            source,
+                            offset + cell.start..offset + cell.end,
+                            SourceKind::Text,
+                            cell_style.clone(),
+                        );
+                        pos = offset + cell.end;
+                    }
+                    highlighter.markup(pos..line_end);
+                }
+                LineKind::Text => highlighter.inline(
+                    source,
+                    offset..line_end,
+                    SourceKind::Text,
+                    InlineStyle::default(),
+                ),
+            }
+        }
+
+        offset += lines[index].len();
+        highlighter.text(line_end..offset);
+    }
+
+    highlighter.spans
+}
+
+#[derive(Default)]
+struct Highlighter {
+    spans: Vec<SourceSpan>,
+}
+
+impl Highlighter {
+    fn push(&mut self, range: Range<usize>, kind: SourceKind, style: InlineStyle) {
+        if !range.is_empty() {
+            self.spans.push(SourceSpan { range, kind, style });
+        }
+    }
+
+    fn text(&mut self, range: Range<usize>) {
+        self.push(range, SourceKind::Text, InlineStyle::default());
+    }
+
+    fn markup(&mut self, range: Range<usize>) {
+        self.push(range, SourceKind::Markup, InlineStyle::default());
+    }
+
+    fn inline(&mut self, source: &str, range: Range<usize>, kind: SourceKind, base: InlineStyle) {
+        let mut pieces = Vec::new();
+        parse_inline_into(source, range, base, &mut pieces);
+        for piece in pieces {
+            let kind = if piece.markup {
+                SourceKind::Markup
+            } else {
+                kind
+            };
+            self.push(piece.range, kind, piece.style);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(spans: &[InlineSpan]) -> String {
+        spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    /// Text of the spans that satisfy `predicate`.
+    fn styled(spans: &[InlineSpan], predicate: impl Fn(&InlineStyle) -> bool) -> Vec<&str> {
+        spans
+            .iter()
+            .filter(|span| predicate(&span.style))
+            .map(|span| span.text.as_str())
+            .collect()
+    }
+
+    fn paragraph(source: &str) -> Vec<InlineSpan> {
+        match parse_blocks(source).as_slice() {
+            [Block::Paragraph(spans)] => spans.clone(),
+            blocks => panic!("expected one paragraph, got {blocks:?}"),
+        }
+    }
+
+    #[test]
+    fn headings() {
+        let blocks = parse_blocks("# Title\n## Sub *title*  \n####### seven\n#nospace");
+
+        let [
+            Block::Heading {
+                level: 1,
+                content: title,
+            },
+            Block::Heading {
+                level: 2,
+                content: sub,
+            },
+            Block::Paragraph(rest),
+        ] = blocks.as_slice()
+        else {
+            panic!("unexpected blocks {blocks:?}");
+        };
+        assert_eq!(plain(title), "Title");
+        assert_eq!(plain(sub), "Sub title");
+        assert_eq!(styled(sub, |style| style.italic), ["title"]);
+        assert_eq!(plain(rest), "####### seven #nospace");
+    }
+
+    #[test]
+    fn nested_emphasis() {
+        let spans = paragraph("**bold *both* bold** and ***all***");
+
+        assert_eq!(plain(&spans), "bold both bold and all");
+        assert_eq!(
+            styled(&spans, |style| style.bold),
+            ["bold ", "both", " bold", "all"]
+        );
+        assert_eq!(styled(&spans, |style| style.italic), ["both", "all"]);
+    }
+
+    #[test]
+    fn unclosed_emphasis_stays_literal() {
+        let spans = paragraph("2 * 3 and **half");
+
+        assert_eq!(plain(&spans), "2 * 3 and **half");
+        assert!(
+            spans
+                .iter()
+                .all(|span| span.style == InlineStyle::default())
+        );
+    }
+
+    #[test]
+    fn underscores_inside_words() {
+        let spans = paragraph("call snake_case_name or _this_");
+
+        assert_eq!(plain(&spans), "call snake_case_name or this");
+        assert_eq!(styled(&spans, |style| style.italic), ["this"]);
+    }
+
+    #[test]
+    fn escapes() {
+        let spans = paragraph(r"\*not italic\* and \_nor this\_ or \`code\`");
+
+        assert_eq!(plain(&spans), "*not italic* and _nor this_ or `code`");
+        assert!(
+            spans
+                .iter()
+                .all(|span| span.style == InlineStyle::default())
+        );
+    }
+
+    #[test]
+    fn links() {
+        let spans = paragraph(
+            "see [the **docs**](https://example.com \"Title\") or https://rust-lang.org.",
+        );
+
+        assert_eq!(plain(&spans), "see the docs or https://rust-lang.org.");
+        let links = spans
+            .iter()
+            .filter_map(|span| Some((span.text.as_str(), span.style.link.as_deref()?)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [
+                ("the ", "https://example.com"),
+                ("docs", "https://example.com"),
+                ("https://rust-lang.org", "https://rust-lang.org"),
+            ]
+        );
+        assert_eq!(styled(&spans, |style| style.bold), ["docs"]);
+    }
+
+    #[test]
+    fn brackets_without_a_target_are_not_links() {
+        let spans = paragraph("[x] and [label] (space) and [open");
+
+        assert_eq!(plain(&spans), "[x] and [label] (space) and [open");
+        assert!(spans.iter().all(|span| span.style.link.is_none()));
+    }
+
+    #[test]
+    fn fenced_code() {
+        let blocks = parse_blocks("```rust\nlet x = *y*;\n\n```\nafter");
+
+        assert_eq!(
+            blocks[0],
+            Block::Code {
+                language: "rust".to_string(),
+                code: "let x = *y*;\n".to_string(),
+            }
+        );
+        assert!(matches!(&blocks[1], Block::Paragraph(spans) if plain(spans) == "after"));
+    }
+
+    #[test]
+    fn unclosed_fence_runs_to_the_end() {
+        let blocks = parse_blocks("text\n~~~\ncode\n# not a heading");
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(
+            blocks[1],
+            Block::Code {
+                language: String::new(),
+                code: "code\n# not a heading".to_string(),
+            }
+        );
+
+        let source = "~~~\ncode\n# not a heading";
+        let kinds = highlight(source)
+            .into_iter()
+            .filter(|span| span.range.start >= 4)
+            .map(|span| span.kind)
+            .collect::<Vec<_>>();
+        assert!(
+            kinds
+                .iter()
+                .all(|kind| matches!(kind, SourceKind::CodeBlock | SourceKind::Text))
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let blocks =
+            parse_blocks("| Name | Qty |\n| :--- | ---: |\n| *a* | 1 |\n| b \\| c | 2 |\n\nafter");
+
+        let [Block::Table(table), Block::Paragraph(_)] = blocks.as_slice() else {
+            panic!("unexpected blocks {blocks:?}");
+        };
+        assert_eq!(table.alignments, [Alignment::Left, Alignment::Right]);
+        let cells =
+            |row: &Vec<Vec<InlineSpan>>| row.iter().map(|cell| plain(cell)).collect::<Vec<_>>();
+        assert_eq!(cells(&table.header), ["Name", "Qty"]);
+        assert_eq!(cells(&table.rows[0]), ["a", "1"]);
+        assert_eq!(styled(&table.rows[0][0], |style| style.italic), ["a"]);
+        assert_eq!(cells(&table.rows[1]), ["b | c", "2"]);
+    }
+
+    #[test]
+    fn lists() {
+        let blocks = parse_blocks("- [x] done\n  - nested\n    continued\n3. third");
+
+        let [Block::List(items)] = blocks.as_slice() else {
+            panic!("unexpected blocks {blocks:?}");
+        };
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            (items[0].checked, plain(&items[0].content).as_str()),
+            (Some(true), "done")
+        );
+        assert_eq!(items[1].depth, 1);
+        assert_eq!(plain(&items[1].content), "nested continued");
+        assert_eq!(items[2].marker, ListMarker::Number(3));
+    }
+
+    #[test]
+    fn non_ascii_text() {
+        let blocks = parse_blocks("# Grüße\n\n**日本語** and _ñ_ café → *ß*");
+
+        assert!(matches!(&blocks[0], Block::Heading { content, .. } if plain(content) == "Grüße"));
+        let Block::Paragraph(spans) = &blocks[1] else {
+            panic!("unexpected blocks {blocks:?}");
+        };
+        assert_eq!(plain(spans), "日本語 and ñ café → ß");
+        assert_eq!(styled(spans, |style| style.bold), ["日本語"]);
+        assert_eq!(styled(spans, |style| style.italic), ["ñ", "ß"]);
+    }
+
+    #[test]
+    fn highlight_marks_syntax() {
+        let spans = highlight("## Hi **there**");
+
+        let kinds = spans
+            .iter()
+            .map(|span| (span.range.clone(), span.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (0..3, SourceKind::Markup),
+                (3..6, SourceKind::Heading(2)),
+                (6..8, SourceKind::Markup),
+                (8..13, SourceKind::Heading(2)),
+                (13..15, SourceKind::Markup),
+            ]
+        );
+        assert!(spans[3].style.bold);
+    }
+
+    #[test]
+    fn highlight_spans_cover_the_source_exactly() {
+        let sources = [
+            "",
+            "plain",
+            "# Title\n\nSome **bold** and _italic_ with `code` and [a link](https://x.y).\n",
+            "> quote *here*\r\n> more\r\n",
+            "- [ ] task\n  - nested `x`\n1. one\n\n---\n",
+            "```rust\nfn main() {}\n```\n~~~\nunclosed\n",
+            "| a | b |\n|---|:-:|\n| 1 \\| 2 | **3** |\ntrailing",
+            "Grüße **日本語** _ñ_ → https://example.com/ü. \\* done",
+            "**unclosed [link](",
+            "\n\n\n",
+        ];
+
+        for source in sources {
+            let mut pos = 0;
+            for span in highlight(source) {
+                assert_eq!(span.range.start, pos, "gap or overlap in {source:?}");
+                assert!(
+                    span.range.end > span.range.start,
+                    "empty span in {source:?}"
+                );
+                assert!(
+                    source.is_char_boundary(span.range.end),
+                    "split char in {source:?}"
+                );
+                pos = span.range.end;
+            }
+            assert_eq!(pos, source.len(), "{source:?} is not fully covered");
+        }
+    }
+}
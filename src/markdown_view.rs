@@ -0,0 +1,299 @@
+use std::{hash::Hash, sync::Arc};
+
+use eframe::egui::{
+    self, Color32, RichText, Ui,
+    cache::{ComputerMut, FrameCache},
+    text::LayoutJob,
+};
+
+use crate::{
+    markdown::{
+        self, Alignment, Block, InlineSpan, InlineStyle, ListItem, ListMarker, SourceKind,
+        SourceSpan, Table,
+    },
+    rich_text_format::font_id,
+};
+
+const SOURCE_FONT_SIZE: f32 = 15.0;
+const PREVIEW_FONT_SIZE: f32 = 16.0;
+
+const MARKUP_COLOR: Color32 = Color32::from_rgb(148, 163, 184);
+const LINK_COLOR: Color32 = Color32::from_rgb(37, 99, 235);
+const CODE_BACKGROUND: Color32 = Color32::from_rgb(241, 245, 249);
+const QUOTE_BAR_COLOR: Color32 = Color32::from_rgb(203, 213, 225);
+
+#[derive(Default)]
+struct Highlighter;
+
+impl ComputerMut<&str, Arc<Vec<SourceSpan>>> for Highlighter {
+    fn compute(&mut self, source: &str) -> Arc<Vec<SourceSpan>> {
+        Arc::new(markdown::highlight(source))
+    }
+}
+
+#[derive(Default)]
+struct Parser;
+
+impl ComputerMut<&str, Arc<Vec<Block>>> for Parser {
+    fn compute(&mut self, source: &str) -> Arc<Vec<Block>> {
+        Arc::new(markdown::parse_blocks(source))
+    }
+}
+
+/// Lays out Markdown source with its syntax highlighted. The source stays plain
+/// text, so every character keeps its place for carets and selections. The
+/// highlighting is cached by text, so it only runs again after an edit.
+pub fn highlight_job(ctx: &egui::Context, text: &str, visuals: &egui::Visuals) -> LayoutJob {
+    let spans = ctx.memory_mut(|mem| {
+        mem.caches
+            .cache::<FrameCache<Arc<Vec<SourceSpan>>, Highlighter>>()
+            .get(text)
+    });
+
+    let mut job = LayoutJob::default();
+    let mut pos = 0;
+    for span in spans.iter() {
+        if span.range.start < pos || span.range.end > text.len() {
+            continue;
+        }
+        job.append(
+            &text[span.range.clone()],
+            0.0,
+            source_format(span.kind, &span.style, visuals),
+        );
+        pos = span.range.end;
+    }
+    if pos < text.len() {
+        job.append(
+            &text[pos..],
+            0.0,
+            source_format(SourceKind::Text, &InlineStyle::default(), visuals),
+        );
+    }
+
+    job
+}
+
+fn source_format(
+    kind: SourceKind,
+    style: &InlineStyle,
+    visuals: &egui::Visuals,
+) -> egui::TextFormat {
+    let color = match kind {
+        SourceKind::Markup => MARKUP_COLOR,
+        _ if style.link.is_some() => LINK_COLOR,
+        SourceKind::Heading(_) => visuals.strong_text_color(),
+        SourceKind::Quote => Color32::from_rgb(71, 85, 105),
+        _ => visuals.text_color(),
+    };
+    let bold = kind != SourceKind::Markup && (style.bold || matches!(kind, SourceKind::Heading(_)));
+
+    egui::TextFormat {
+        font_id: font_id(SOURCE_FONT_SIZE, bold, true),
+        color,
+        italics: style.italic || kind == SourceKind::Quote,
+        underline: if style.link.is_some() && kind != SourceKind::Markup {
+            egui::Stroke::new(1.0, color)
+        } else {
+            egui::Stroke::NONE
+        },
+        strikethrough: if style.strikethrough && kind != SourceKind::Markup {
+            egui::Stroke::new(1.0, color)
+        } else {
+            egui::Stroke::NONE
+        },
+        background: if style.code || kind == SourceKind::CodeBlock {
+            CODE_BACKGROUND
+        } else {
+            Color32::TRANSPARENT
+        },
+        ..Default::default()
+    }
+}
+
+/// Renders Markdown source as formatted text. The parse is cached by text, so
+/// remote edits show up as soon as they arrive without parsing every frame.
+pub fn render_markdown_preview(ui: &mut Ui, source: &str, id_salt: impl Hash) {
+    egui::ScrollArea::vertical()
+        .id_salt(id_salt)
+        .max_height(ui.available_height())
+        .auto_shrink([false, false])
+        .show(ui, |ui| {
+            let blocks = ui.memory_mut(|mem| {
+                mem.caches
+                    .cache::<FrameCache<Arc<Vec<Block>>, Parser>>()
+                    .get(source)
+            });
+            if blocks.is_empty() {
+                ui.label(
+                    RichText::new("Nothing to preview yet")
+                        .size(14.0)
+                        .italics()
+                        .color(egui::Color32::from_rgb(100, 116, 139)),
+                );
+            }
+
+            for (index, block) in blocks.iter().enumerate() {
+                if index > 0 {
+                    ui.add_space(10.0);
+                }
+                render_block(ui, block, index);
+            }
+        });
+}
+
+fn render_block(ui: &mut Ui, block: &Block, index: usize) {
+    match block {
+        Block::Heading { level, content } => {
+            let size = match level {
+                1 => 30.0,
+                2 => 24.0,
+                3 => 20.0,
+                4 => 18.0,
+                _ => PREVIEW_FONT_SIZE,
+            };
+            render_inline(ui, content, size, true);
+        }
+        Block::Paragraph(content) => render_inline(ui, content, PREVIEW_FONT_SIZE, false),
+        Block::Quote(content) => {
+            let response = egui::Frame::new()
+                .inner_margin(egui::Margin {
+                    left: 14,
+                    ..Default::default()
+                })
+                .show(ui, |ui| {
+                    render_inline(ui, content, PREVIEW_FONT_SIZE, false);
+                })
+                .response;
+            let rect = response.rect;
+            ui.painter().vline(
+                rect.left() + 2.0,
+                rect.y_range(),
+                egui::Stroke::new(3.0, QUOTE_BAR_COLOR),
+            );
+        }
+        Block::List(items) => {
+            for item in items {
+                render_list_item(ui, item);
+            }
+        }
+        Block::Code { code, .. } => {
+            egui::Frame::new()
+                .fill(CODE_BACKGROUND)
+                .corner_radius(6)
+                .inner_margin(egui::vec2(10.0, 8.0))
+                .show(ui, |ui| {
+                    ui.set_width(ui.available_width());
+                    ui.label(
+                        RichText::new(code)
+                            .monospace()
+                            .size(14.0)
+                            .color(Color32::from_rgb(30, 41, 59)),
+                    );
+                });
+        }
+        Block::Table(table) => render_table(ui, table, index),
+        Block::Rule => {
+            ui.separator();
+        }
+    }
+}
+
+fn render_list_item(ui: &mut Ui, item: &ListItem) {
+    ui.horizontal_top(|ui| {
+        ui.add_space(4.0 + item.depth as f32 * 20.0);
+
+        let marker = match (item.checked, item.marker) {
+            (Some(true), _) => "☑".to_string(),
+            (Some(false), _) => "☐".to_string(),
+            (None, ListMarker::Bullet) => "•".to_string(),
+            (None, ListMarker::Number(number)) => format!("{number}."),
+        };
+        ui.label(
+            RichText::new(marker)
+                .size(PREVIEW_FONT_SIZE)
+                .color(egui::Color32::from_rgb(100, 116, 139)),
+        );
+
+        render_inline(ui, &item.content, PREVIEW_FONT_SIZE, false);
+    });
+}
+
+fn render_table(ui: &mut Ui, table: &Table, index: usize) {
+    let columns = table
+        .rows
+        .iter()
+        .map(Vec::len)
+        .chain([table.header.len()])
+        .max()
+        .unwrap_or_default();
+
+    egui::Frame::new()
+        .stroke(egui::Stroke::new(
+            1.0,
+            egui::Color32::from_rgb(226, 232, 240),
+        ))
+        .corner_radius(6)
+        .inner_margin(egui::vec2(8.0, 6.0))
+        .show(ui, |ui| {
+            egui::Grid::new(("markdown_table", index))
+                .num_columns(columns)
+                .striped(true)
+                .spacing(egui::vec2(16.0, 6.0))
+                .show(ui, |ui| {
+                    for (row_index, row) in
+                        [&table.header].into_iter().chain(&table.rows).enumerate()
+                    {
+                        for column in 0..columns {
+                            let align = match table.alignments.get(column) {
+                                Some(Alignment::Center) => egui::Align::Center,
+                                Some(Alignment::Right) => egui::Align::Max,
+                                _ => egui::Align::Min,
+                            };
+                            let cell = row.get(column).map(Vec::as_slice).unwrap_or_default();
+                            ui.with_layout(egui::Layout::top_down(align), |ui| {
+                                render_inline(ui, cell, PREVIEW_FONT_SIZE - 1.0, row_index == 0);
+                            });
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+/// Lays out a line of formatted text, wrapping it and making links clickable.
+/// `strong` text, like headings, is bold in the strong text colour as well.
+fn render_inline(ui: &mut Ui, spans: &[InlineSpan], size: f32, strong: bool) {
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+
+        for span in spans {
+            let bold = span.style.bold || strong;
+            let mut text = if span.style.code {
+                RichText::new(&span.text)
+                    .font(font_id(size * 0.9, bold, true))
+                    .background_color(CODE_BACKGROUND)
+            } else {
+                RichText::new(&span.text).font(font_id(size, bold, false))
+            };
+            if strong {
+                text = text.color(ui.visuals().strong_text_color());
+            }
+            if span.style.italic {
+                text = text.italics();
+            }
+            if span.style.strikethrough {
+                text = text.strikethrough();
+            }
+
+            match &span.style.link {
+                Some(url) => {
+                    ui.hyperlink_to(text.color(LINK_COLOR), url);
+                }
+                None => {
+                    ui.label(text);
+                }
+            }
+        }
+    });
+}
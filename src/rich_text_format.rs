@@ -0,0 +1,250 @@
+use std::ops::Range;
+
+use eframe::egui::{
+    self, Color32, FontFamily, FontId, Key, KeyboardShortcut, Modifiers, RichText, Ui,
+    text::LayoutJob,
+};
+
+use rusttalk_core::rich_text::{StyledSpan, TextAttributes, TextStyle};
+
+const BOLD_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::B);
+const ITALIC_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::I);
+const UNDERLINE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::U);
+const STRIKETHROUGH_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::X);
+const CODE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::E);
+const LINK_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::K);
+const HEADING_SHORTCUTS: [(u8, KeyboardShortcut); 3] = [
+    (
+        1,
+        KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::ALT), Key::Num1),
+    ),
+    (
+        2,
+        KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::ALT), Key::Num2),
+    ),
+    (
+        3,
+        KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::ALT), Key::Num3),
+    ),
+];
+
+const BASE_FONT_SIZE: f32 = 18.0;
+
+/// Font families holding the bundled bold faces, registered at startup since
+/// egui's own fonts come in one weight only.
+pub const BOLD_FAMILY: &str = "bold";
+pub const BOLD_MONOSPACE_FAMILY: &str = "bold-monospace";
+
+/// Picks the regular or bold face of the proportional or monospace font.
+pub fn font_id(size: f32, bold: bool, monospace: bool) -> FontId {
+    let family = match (bold, monospace) {
+        (false, false) => FontFamily::Proportional,
+        (false, true) => FontFamily::Monospace,
+        (true, false) => FontFamily::Name(BOLD_FAMILY.into()),
+        (true, true) => FontFamily::Name(BOLD_MONOSPACE_FAMILY.into()),
+    };
+    FontId::new(size, family)
+}
+
+pub enum FormatAction {
+    Toggle(TextStyle),
+    Heading(u8),
+    /// Opens the link editor for the selection.
+    Link,
+}
+
+/// Link being edited for a fixed range, since focus moves to the URL field.
+pub struct LinkEdit {
+    pub range: Range<usize>,
+    pub url: String,
+}
+
+fn style_shortcut(style: TextStyle) -> KeyboardShortcut {
+    match style {
+        TextStyle::Bold => BOLD_SHORTCUT,
+        TextStyle::Italic => ITALIC_SHORTCUT,
+        TextStyle::Underline => UNDERLINE_SHORTCUT,
+        TextStyle::Strikethrough => STRIKETHROUGH_SHORTCUT,
+        TextStyle::Code => CODE_SHORTCUT,
+    }
+}
+
+/// Takes formatting shortcuts away from the text edit.
+pub fn consume_format_shortcuts(ui: &mut Ui) -> Option<FormatAction> {
+    ui.input_mut(|input| {
+        for style in TextStyle::ALL {
+            if input.consume_shortcut(&style_shortcut(style)) {
+                return Some(FormatAction::Toggle(style));
+            }
+        }
+        for (level, shortcut) in HEADING_SHORTCUTS {
+            if input.consume_shortcut(&shortcut) {
+                return Some(FormatAction::Heading(level));
+            }
+        }
+        if input.consume_shortcut(&LINK_SHORTCUT) {
+            return Some(FormatAction::Link);
+        }
+
+        None
+    })
+}
+
+pub fn render_format_toolbar(ui: &mut Ui, active: &TextAttributes) -> Option<FormatAction> {
+    let mut action = None;
+
+    ui.horizontal(|ui| {
+        ui.spacing_mut().item_spacing.x = 4.0;
+
+        for style in TextStyle::ALL {
+            let label = match style {
+                TextStyle::Bold => RichText::new("B").font(font_id(13.0, true, false)),
+                TextStyle::Italic => RichText::new("I").italics(),
+                TextStyle::Underline => RichText::new("U").underline(),
+                TextStyle::Strikethrough => RichText::new("S").strikethrough(),
+                TextStyle::Code => RichText::new("</>").monospace(),
+            };
+            let button = egui::Button::new(label.size(13.0))
+                .min_size(egui::vec2(28.0, 28.0))
+                .corner_radius(6)
+                .selected(active.has(style));
+            let hover = format!(
+                "{style:?} ({})",
+                ui.ctx().format_shortcut(&style_shortcut(style))
+            );
+            if ui.add(button).on_hover_text(hover).clicked() {
+                action = Some(FormatAction::Toggle(style));
+            }
+        }
+
+        ui.add_space(8.0);
+
+        for (level, shortcut) in HEADING_SHORTCUTS {
+            let button = egui::Button::new(RichText::new(format!("H{level}")).size(13.0))
+                .min_size(egui::vec2(28.0, 28.0))
+                .corner_radius(6)
+                .selected(active.heading == Some(level));
+            let hover = format!("Heading {level} ({})", ui.ctx().format_shortcut(&shortcut));
+            if ui.add(button).on_hover_text(hover).clicked() {
+                action = Some(FormatAction::Heading(level));
+            }
+        }
+
+        ui.add_space(8.0);
+
+        let link_button = egui::Button::new(RichText::new("🔗").size(13.0))
+            .min_size(egui::vec2(28.0, 28.0))
+            .corner_radius(6)
+            .selected(active.link.is_some());
+        let hover = format!(
+            "Link ({}). {}-click a link to open it",
+            ui.ctx().format_shortcut(&LINK_SHORTCUT),
+            ui.ctx().format_modifiers(Modifiers::COMMAND)
+        );
+        if ui.add(link_button).on_hover_text(hover).clicked() {
+            action = Some(FormatAction::Link);
+        }
+    });
+
+    action
+}
+
+/// Attributes of the character just before `pos`, which is what typing there continues.
+pub fn attributes_at(spans: &[StyledSpan], pos: usize) -> TextAttributes {
+    let pos = pos.saturating_sub(1);
+    spans
+        .iter()
+        .find(|span| span.range.contains(&pos))
+        .map(|span| span.attributes.clone())
+        .unwrap_or_default()
+}
+
+/// Link under the character at `pos`.
+pub fn link_at(spans: &[StyledSpan], pos: usize) -> Option<&str> {
+    spans
+        .iter()
+        .find(|span| span.range.contains(&pos))
+        .and_then(|span| span.attributes.link.as_deref())
+}
+
+/// Lays out the editor text with the document's marks. The text edit's buffer can
+/// be a keystroke ahead of the document, so spans are clamped to it.
+pub fn layout_job(text: &str, spans: &[StyledSpan], visuals: &egui::Visuals) -> LayoutJob {
+    let byte_offsets = text
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain([text.len()])
+        .collect::<Vec<_>>();
+    let char_len = byte_offsets.len() - 1;
+
+    let mut job = LayoutJob::default();
+    let mut pos = 0;
+    for span in spans {
+        let start = span.range.start.min(char_len);
+        let end = span.range.end.min(char_len);
+        if start < end {
+            job.append(
+                &text[byte_offsets[start]..byte_offsets[end]],
+                0.0,
+                text_format(&span.attributes, visuals),
+            );
+        }
+        pos = pos.max(end);
+    }
+    if pos < char_len {
+        job.append(
+            &text[byte_offsets[pos]..],
+            0.0,
+            text_format(&TextAttributes::default(), visuals),
+        );
+    }
+
+    job
+}
+
+fn text_format(attributes: &TextAttributes, visuals: &egui::Visuals) -> egui::TextFormat {
+    let size = match attributes.heading {
+        Some(1) => 30.0,
+        Some(2) => 24.0,
+        Some(3) => 20.0,
+        _ => BASE_FONT_SIZE,
+    };
+    let bold = attributes.bold || attributes.heading.is_some();
+    let font_id = if attributes.code {
+        font_id(size * 0.9, bold, true)
+    } else {
+        font_id(size, bold, false)
+    };
+
+    let color = if attributes.link.is_some() {
+        Color32::from_rgb(37, 99, 235)
+    } else if attributes.heading.is_some() {
+        visuals.strong_text_color()
+    } else {
+        visuals.text_color()
+    };
+    let line = egui::Stroke::new(1.0, color);
+
+    egui::TextFormat {
+        font_id,
+        color,
+        italics: attributes.italic,
+        underline: if attributes.underline || attributes.link.is_some() {
+            line
+        } else {
+            egui::Stroke::NONE
+        },
+        strikethrough: if attributes.strikethrough {
+            line
+        } else {
+            egui::Stroke::NONE
+        },
+        background: if attributes.code {
+            Color32::from_rgb(241, 245, 249)
+        } else {
+            Color32::TRANSPARENT
+        },
+        ..Default::default()
+    }
+}
@@ -0,0 +1,50 @@
+use eframe::egui::{self, RichText, Ui};
+use rusttalk_core::SetupStage;
+use tokio::sync::{oneshot, watch};
+
+/// Shown while a session is being set up or torn down.
+#[derive(Default)]
+pub struct LoadingState {
+    pub progress: Option<watch::Receiver<SetupStage>>,
+    /// Present while setup can still be cancelled.
+    pub cancel: Option<oneshot::Sender<()>>,
+    pub cancelling: bool,
+}
+
+pub fn render_loading(ui: &mut Ui, state: &mut LoadingState) {
+    ui.vertical_centered(|ui| {
+        ui.add_space(160.0);
+
+        ui.spinner();
+
+        ui.add_space(16.0);
+
+        let status = if state.cancelling {
+            "Cancelling…".to_string()
+        } else if let Some(progress) = &state.progress {
+            progress.borrow().to_string()
+        } else {
+            "Please wait…".to_string()
+        };
+        ui.label(
+            RichText::new(status)
+                .size(14.0)
+                .color(egui::Color32::from_rgb(100, 116, 139)),
+        );
+
+        if state.cancel.is_some() {
+            ui.add_space(16.0);
+
+            let cancel_button = egui::Button::new(RichText::new("Cancel").size(12.0))
+                .min_size(egui::vec2(80.0, 28.0))
+                .corner_radius(6);
+
+            if ui.add(cancel_button).clicked()
+                && let Some(cancel) = state.cancel.take()
+            {
+                let _ = cancel.send(());
+                state.cancelling = true;
+            }
+        }
+    });
+}
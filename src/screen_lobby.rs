@@ -1,11 +1,59 @@
 use eframe::egui::{self, RichText, Ui};
 
-use crate::{task_start_session::task_start_session, App};
+use std::error::Error;
+
+use rusttalk_core::{
+    Profile, SessionStart, SetupError,
+    awareness::{IdBytes, short_id},
+    document_store::{self, RecentDocument},
+    identity, profile,
+};
+
+use crate::{App, screen_session::hue_color, task_start_session::task_start_session};
+
+/// Hues offered in the profile color picker, evenly spread around the wheel.
+const PROFILE_HUES: [u16; 12] = [0, 30, 60, 90, 120, 150, 180, 210, 240, 270, 300, 330];
 
 pub struct LobbyState {
     pub join_existing: bool,
-    pub name_input: String,
-    pub existing_peer_input: String,
+    /// Saved when a session starts, so it is remembered next time.
+    pub profile: Profile,
+    pub ticket_input: String,
+    pub recent_documents: Vec<RecentDocument>,
+    pub failed_start: Option<Box<FailedStart>>,
+    /// Public id of the stored identity, which decides the color peers see.
+    pub identity: Option<IdBytes>,
+    pub identity_import_input: String,
+    pub identity_importing: bool,
+    /// Asking before the stored identity is replaced, since that cannot be undone.
+    pub identity_rotating: bool,
+    pub identity_notice: Option<String>,
+}
+
+/// The last attempt to start a session, kept so it can be retried as-is.
+pub struct FailedStart {
+    pub error: SetupError,
+    pub profile: Profile,
+    pub start: SessionStart,
+}
+
+impl LobbyState {
+    pub fn new() -> Self {
+        Self {
+            join_existing: false,
+            profile: profile::load(),
+            ticket_input: String::new(),
+            recent_documents: document_store::load_recent_documents(),
+            failed_start: None,
+            identity: identity::load_or_create()
+                .ok()
+                .map(|secret_key| *secret_key.public().as_bytes()),
+            identity_import_input: String::new(),
+            identity_importing: false,
+            identity_rotating: false,
+            identity_notice: None,
+        }
+    }
 }
 
 pub fn render_lobby(ui: &mut Ui, app: App, state: &mut LobbyState) {
@@ -75,6 +123,22 @@ pub fn render_lobby(ui: &mut Ui, app: App, state: &mut LobbyState) {
 
                 ui.add_space(32.0);
 
+                if let Some(failed_start) = &state.failed_start {
+                    match render_setup_error(ui, &failed_start.error) {
+                        Some(ErrorAction::Retry) => {
+                            tokio::spawn(task_start_session(
+                                app.clone(),
+                                failed_start.profile.clone(),
+                                failed_start.start.clone(),
+                            ));
+                        }
+                        Some(ErrorAction::Dismiss) => state.failed_start = None,
+                        None => {}
+                    }
+
+                    ui.add_space(20.0);
+                }
+
                 // Name input
                 ui.horizontal(|ui| {
                     ui.set_width(400.0);
@@ -87,20 +151,28 @@ pub fn render_lobby(ui: &mut Ui, app: App, state: &mut LobbyState) {
 
                 ui.add_space(4.0);
 
-                let name_edit = egui::TextEdit::singleline(&mut state.name_input)
+                let name_edit = egui::TextEdit::singleline(&mut state.profile.name)
                     .desired_width(400.0)
                     .font(egui::FontId::new(16.0, egui::FontFamily::Proportional))
                     .margin(egui::vec2(12.0, 12.0));
                 ui.add(name_edit);
 
+                ui.add_space(8.0);
+
+                render_profile_style(ui, &mut state.profile);
+
+                ui.add_space(12.0);
+
+                render_identity(ui, state);
+
                 ui.add_space(20.0);
 
-                // Peer ID input (only for join)
+                // Session ticket input (only for join)
                 if state.join_existing {
                     ui.horizontal(|ui| {
                         ui.set_width(400.0);
                         ui.label(
-                            RichText::new("Peer ID to join")
+                            RichText::new("Session ticket to join")
                                 .size(14.0)
                                 .color(egui::Color32::from_rgb(71, 85, 105)),
                         );
@@ -108,11 +180,11 @@ pub fn render_lobby(ui: &mut Ui, app: App, state: &mut LobbyState) {
 
                     ui.add_space(4.0);
 
-                    let peer_edit = egui::TextEdit::singleline(&mut state.existing_peer_input)
+                    let ticket_edit = egui::TextEdit::singleline(&mut state.ticket_input)
                         .desired_width(400.0)
                         .font(egui::FontId::new(16.0, egui::FontFamily::Proportional))
                         .margin(egui::vec2(12.0, 12.0));
-                    ui.add(peer_edit);
+                    ui.add(ticket_edit);
 
                     ui.add_space(20.0);
                 }
@@ -129,17 +201,323 @@ pub fn render_lobby(ui: &mut Ui, app: App, state: &mut LobbyState) {
                     .corner_radius(8);
 
                 if ui.add(button).clicked() {
-                    if state.join_existing {
-                        tokio::spawn(task_start_session(
-                            app,
-                            state.name_input.clone(),
-                            Some(state.existing_peer_input.clone()),
-                        ));
+                    let start = if state.join_existing {
+                        SessionStart::Join {
+                            ticket: state.ticket_input.clone(),
+                        }
                     } else {
-                        tokio::spawn(task_start_session(app, state.name_input.clone(), None));
+                        SessionStart::Create
+                    };
+                    tokio::spawn(task_start_session(
+                        app.clone(),
+                        state.profile.clone(),
+                        start,
+                    ));
+                }
+
+                // Recently opened documents
+                if !state.recent_documents.is_empty() {
+                    ui.add_space(32.0);
+
+                    ui.horizontal(|ui| {
+                        ui.set_width(400.0);
+                        ui.label(
+                            RichText::new("Recent documents")
+                                .size(14.0)
+                                .color(egui::Color32::from_rgb(71, 85, 105)),
+                        );
+                    });
+
+                    ui.add_space(4.0);
+
+                    for document in &state.recent_documents {
+                        ui.horizontal(|ui| {
+                            ui.set_width(400.0);
+                            ui.label(
+                                RichText::new(&document.title)
+                                    .size(14.0)
+                                    .color(egui::Color32::from_rgb(30, 41, 59)),
+                            );
+
+                            ui.with_layout(
+                                egui::Layout::right_to_left(egui::Align::Center),
+                                |ui| {
+                                    let open_button =
+                                        egui::Button::new(RichText::new("Open").size(12.0))
+                                            .min_size(egui::vec2(80.0, 28.0))
+                                            .corner_radius(6);
+
+                                    if ui.add(open_button).clicked() {
+                                        tokio::spawn(task_start_session(
+                                            app.clone(),
+                                            state.profile.clone(),
+                                            SessionStart::Reopen(document.clone()),
+                                        ));
+                                    }
+                                },
+                            );
+                        });
                     }
                 }
             },
         );
     });
 }
+
+enum ErrorAction {
+    Retry,
+    Dismiss,
+}
+
+fn render_setup_error(ui: &mut Ui, error: &SetupError) -> Option<ErrorAction> {
+    let mut action = None;
+
+    egui::Frame::new()
+        .fill(egui::Color32::from_rgb(254, 242, 242))
+        .stroke(egui::Stroke::new(
+            1.0,
+            egui::Color32::from_rgb(252, 165, 165),
+        ))
+        .corner_radius(8)
+        .inner_margin(egui::vec2(12.0, 12.0))
+        .show(ui, |ui| {
+            ui.set_width(376.0);
+            ui.vertical(|ui| {
+                ui.label(
+                    RichText::new(error.to_string())
+                        .size(14.0)
+                        .strong()
+                        .color(egui::Color32::from_rgb(153, 27, 27)),
+                );
+                ui.label(
+                    RichText::new(error.hint())
+                        .size(13.0)
+                        .color(egui::Color32::from_rgb(71, 85, 105)),
+                );
+                if let Some(source) = error.source() {
+                    ui.label(
+                        RichText::new(source.to_string())
+                            .size(12.0)
+                            .monospace()
+                            .color(egui::Color32::from_rgb(100, 116, 139)),
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    let retry_button = egui::Button::new(RichText::new("Retry").size(12.0))
+                        .min_size(egui::vec2(80.0, 28.0))
+                        .corner_radius(6);
+                    if ui.add(retry_button).clicked() {
+                        action = Some(ErrorAction::Retry);
+                    }
+
+                    let dismiss_button = egui::Button::new(RichText::new("Dismiss").size(12.0))
+                        .min_size(egui::vec2(80.0, 28.0))
+                        .corner_radius(6);
+                    if ui.add(dismiss_button).clicked() {
+                        action = Some(ErrorAction::Dismiss);
+                    }
+                });
+            });
+        });
+
+    action
+}
+
+/// Avatar initials and preferred color. Peers whose colors clash are nudged apart
+/// in the session, so the choice is a preference.
+fn render_profile_style(ui: &mut Ui, profile: &mut Profile) {
+    ui.horizontal(|ui| {
+        ui.set_width(400.0);
+        ui.spacing_mut().item_spacing.x = 4.0;
+
+        let default_initials = profile.avatar_initials();
+        let initials_edit = egui::TextEdit::singleline(&mut profile.initials)
+            .hint_text(default_initials)
+            .char_limit(2)
+            .desired_width(32.0)
+            .margin(egui::vec2(6.0, 4.0));
+        ui.add(initials_edit)
+            .on_hover_text("Initials on your avatar. Leave empty to use your name's");
+
+        ui.add_space(8.0);
+
+        let auto_button = egui::Button::new(RichText::new("Auto").size(12.0))
+            .min_size(egui::vec2(40.0, 22.0))
+            .corner_radius(6)
+            .selected(profile.hue.is_none());
+        if ui
+            .add(auto_button)
+            .on_hover_text("Derive your color from your identity")
+            .clicked()
+        {
+            profile.hue = None;
+        }
+
+        for hue in PROFILE_HUES {
+            let color = hue_color(hue);
+            let (rect, response) =
+                ui.allocate_exact_size(egui::vec2(22.0, 22.0), egui::Sense::click());
+            ui.painter().circle_filled(rect.center(), 9.0, color);
+            if profile.hue == Some(hue) {
+                ui.painter()
+                    .circle_stroke(rect.center(), 10.5, egui::Stroke::new(2.0, color));
+            }
+            if response.clicked() {
+                profile.hue = Some(hue);
+            }
+        }
+    });
+}
+
+fn render_identity(ui: &mut Ui, state: &mut LobbyState) {
+    ui.horizontal(|ui| {
+        ui.set_width(400.0);
+
+        match &state.identity {
+            Some(identity) => {
+                let color = hue_color(
+                    state
+                        .profile
+                        .hue
+                        .unwrap_or_else(|| profile::default_hue(identity)),
+                );
+                ui.label(RichText::new("●").size(14.0).color(color));
+                ui.label(
+                    RichText::new(format!("Identity {}", short_id(identity)))
+                        .size(13.0)
+                        .monospace()
+                        .color(egui::Color32::from_rgb(100, 116, 139)),
+                )
+                .on_hover_text("Peers recognize you, and your color, by this id");
+            }
+            None => {
+                ui.label(
+                    RichText::new("No stored identity")
+                        .size(13.0)
+                        .color(egui::Color32::from_rgb(100, 116, 139)),
+                );
+            }
+        }
+
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            let rotate_button = egui::Button::new(RichText::new("New").size(12.0))
+                .min_size(egui::vec2(56.0, 28.0))
+                .corner_radius(6)
+                .selected(state.identity_rotating);
+            if ui
+                .add(rotate_button)
+                .on_hover_text("Start over with a new identity. Peers will see a new user")
+                .clicked()
+            {
+                state.identity_rotating = !state.identity_rotating;
+                state.identity_importing = false;
+            }
+
+            let import_button = egui::Button::new(RichText::new("Import").size(12.0))
+                .min_size(egui::vec2(56.0, 28.0))
+                .corner_radius(6)
+                .selected(state.identity_importing);
+            if ui.add(import_button).clicked() {
+                state.identity_importing = !state.identity_importing;
+                state.identity_rotating = false;
+            }
+
+            let export_button = egui::Button::new(RichText::new("📋 Export").size(12.0))
+                .min_size(egui::vec2(56.0, 28.0))
+                .corner_radius(6);
+            if ui
+                .add(export_button)
+                .on_hover_text("Copies your secret identity key. Anyone holding it can act as you")
+                .clicked()
+            {
+                state.identity_notice = Some(match identity::export() {
+                    Ok(exported) => {
+                        ui.ctx().copy_text(exported);
+                        "Identity copied to the clipboard".to_string()
+                    }
+                    Err(err) => format!("Could not export the identity: {err:#}"),
+                });
+            }
+        });
+    });
+
+    if state.identity_rotating {
+        ui.horizontal(|ui| {
+            ui.set_width(400.0);
+
+            ui.label(
+                RichText::new("Replace your identity? Peers will see you as a new user.")
+                    .size(12.0)
+                    .color(egui::Color32::from_rgb(217, 119, 6)),
+            );
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                let cancel_button = egui::Button::new(RichText::new("Cancel").size(12.0))
+                    .min_size(egui::vec2(56.0, 28.0))
+                    .corner_radius(6);
+                if ui.add(cancel_button).clicked() {
+                    state.identity_rotating = false;
+                }
+
+                let confirm_button = egui::Button::new(
+                    RichText::new("Replace")
+                        .size(12.0)
+                        .color(egui::Color32::WHITE),
+                )
+                .fill(egui::Color32::from_rgb(220, 38, 38))
+                .min_size(egui::vec2(56.0, 28.0))
+                .corner_radius(6);
+                if ui.add(confirm_button).clicked() {
+                    state.identity_rotating = false;
+                    state.identity_notice = Some(match identity::rotate() {
+                        Ok(secret_key) => {
+                            state.identity = Some(*secret_key.public().as_bytes());
+                            "Created a new identity".to_string()
+                        }
+                        Err(err) => format!("Could not create an identity: {err:#}"),
+                    });
+                }
+            });
+        });
+    }
+
+    if state.identity_importing {
+        ui.horizontal(|ui| {
+            ui.set_width(400.0);
+
+            let import_edit = egui::TextEdit::singleline(&mut state.identity_import_input)
+                .hint_text("collabid…")
+                .desired_width(312.0)
+                .margin(egui::vec2(8.0, 6.0));
+            ui.add(import_edit);
+
+            let confirm_button = egui::Button::new(RichText::new("Use").size(12.0))
+                .min_size(egui::vec2(80.0, 28.0))
+                .corner_radius(6);
+            if ui.add(confirm_button).clicked() {
+                state.identity_notice =
+                    Some(match identity::import(&state.identity_import_input) {
+                        Ok(secret_key) => {
+                            state.identity = Some(*secret_key.public().as_bytes());
+                            state.identity_import_input.clear();
+                            state.identity_importing = false;
+                            "Identity imported".to_string()
+                        }
+                        Err(err) => format!("{err:#}"),
+                    });
+            }
+        });
+    }
+
+    if let Some(notice) = &state.identity_notice {
+        ui.horizontal(|ui| {
+            ui.set_width(400.0);
+            ui.label(
+                RichText::new(notice)
+                    .size(12.0)
+                    .color(egui::Color32::from_rgb(100, 116, 139)),
+            );
+        });
+    }
+}
@@ -1,15 +1,50 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use eframe::egui::{
+    self, Color32, Key, KeyboardShortcut, LayerId, Modifiers, RichText, TextEdit, Ui, UiBuilder,
+    text::CCursor,
+};
 
-use eframe::egui::{self, Color32, LayerId, RichText, TextEdit, Ui, UiBuilder, text::CCursor};
+use rusttalk_core::{
+    ConnectionStatus, MessageErrors, Presence, Profile,
+    awareness::{Activity, IdBytes, LoroCursors, short_id},
+    profile,
+    rich_text::{self, StyledSpan},
+    workspace::{self, DocumentFormat},
+};
 
 use crate::{
     App,
-    awareness::{LoroCursors, broadcast_awareness},
+    document_sidebar::{render_document_sidebar, switch_document},
+    history_panel::render_history_window,
+    markdown_view::{highlight_job, render_markdown_preview},
+    rich_text_format::{
+        FormatAction, LinkEdit, attributes_at, consume_format_shortcuts, layout_job, link_at,
+        render_format_toolbar,
+    },
     task_leave_session::task_leave_session,
     task_start_session::SessionState,
 };
 
+/// How long a remote caret keeps its name flag after it last moved.
+const CARET_FLAG_DURATION: Duration = Duration::from_secs(3);
+
+const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+const REDO_ALT_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Y);
+
 pub fn render_session(ui: &mut Ui, app: App, state: &mut SessionState) {
+    let (focused, has_input) = ui.input(|input| (input.focused, !input.events.is_empty()));
+    let _ = state.session.set_focused(focused);
+    if has_input {
+        let _ = state.session.record_input();
+    }
+
     ui.vertical_centered(|ui| {
         // Header with leave button
         ui.horizontal(|ui| {
@@ -22,6 +57,17 @@ pub fn render_session(ui: &mut Ui, app: App, state: &mut SessionState) {
                     .color(egui::Color32::from_rgb(30, 41, 59)),
             );
 
+            ui.label(
+                RichText::new("🔒 End-to-end encrypted")
+                    .size(12.0)
+                    .color(egui::Color32::from_rgb(22, 163, 74)),
+            )
+            .on_hover_text("Only peers with this session's ticket can read its messages");
+
+            render_connection_status(ui, state.session.status());
+            render_message_errors(ui, &state.session.message_errors());
+            render_incompatible_peers(ui, &state.session.presence());
+
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                 let leave_button = egui::Button::new(
                     RichText::new("Leave Session")
@@ -35,25 +81,34 @@ pub fn render_session(ui: &mut Ui, app: App, state: &mut SessionState) {
                 if ui.add(leave_button).clicked() {
                     tokio::spawn(task_leave_session(app));
                 }
+
+                let history_button = egui::Button::new(RichText::new("🕘 History").size(14.0))
+                    .min_size(egui::vec2(100.0, 36.0))
+                    .corner_radius(8)
+                    .selected(state.history.open);
+
+                if ui.add(history_button).clicked() {
+                    state.history.open = !state.history.open;
+                }
             });
         });
 
         ui.add_space(16.0);
 
-        // Peer ID display with copy button
+        // Session ticket display with copy button
         ui.horizontal(|ui| {
             ui.set_width(ui.available_width());
             ui.set_height(32.0);
             ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
                 ui.label(
-                    RichText::new("Your Peer ID:")
+                    RichText::new("Session ticket:")
                         .size(14.0)
                         .color(egui::Color32::from_rgb(71, 85, 105)),
                 );
 
-                let id = state.iroh_endpoint.id().to_string();
+                let ticket = state.session.ticket().to_string();
                 ui.label(
-                    RichText::new(&id[..id.len().min(32)])
+                    RichText::new(&ticket[..ticket.len().min(32)])
                         .size(14.0)
                         .monospace()
                         .color(egui::Color32::from_rgb(100, 116, 139)),
@@ -66,7 +121,7 @@ pub fn render_session(ui: &mut Ui, app: App, state: &mut SessionState) {
                     .corner_radius(6);
 
                 if ui.add(copy_button).clicked() {
-                    ui.ctx().copy_text(id);
+                    ui.ctx().copy_text(ticket);
                 }
             });
         });
@@ -85,108 +140,657 @@ pub fn render_session(ui: &mut Ui, app: App, state: &mut SessionState) {
 
                 ui.add_space(8.0);
 
+                let hues = state.session.peer_hues();
+
                 // Own user
-                let own_color = generate_peer_color(&state.own_id);
-                egui::Frame::new()
-                    .fill(egui::Color32::from_rgba_unmultiplied(
-                        own_color.r(),
-                        own_color.g(),
-                        own_color.b(),
-                        30,
-                    ))
-                    .stroke(egui::Stroke::new(1.0, own_color))
-                    .corner_radius(12)
-                    .inner_margin(egui::vec2(8.0, 2.0))
-                    .show(ui, |ui| {
-                        ui.label(RichText::new(&state.own_name).size(12.0).color(own_color));
-                    });
+                let own_color = peer_color(&hues, &state.session.own_id());
+                let own_activity = state.session.presence().own_activity.activity();
+                render_user_chip(ui, state.session.own_profile(), own_activity, own_color);
 
-                // Other peers
+                // Other peers, click to jump to their cursor
+                let mut jump_to = None;
+                let mut toggle_follow = None;
                 state
+                    .session
+                    .presence()
                     .awareness_cache
                     .iter()
                     .for_each(|(_, (awareness, _))| {
                         ui.add_space(6.0);
-                        let peer_color = generate_peer_color(&awareness.endpoint_id);
-                        egui::Frame::new()
-                            .fill(egui::Color32::from_rgba_unmultiplied(
-                                peer_color.r(),
-                                peer_color.g(),
-                                peer_color.b(),
-                                30,
-                            ))
-                            .stroke(egui::Stroke::new(1.0, peer_color))
-                            .corner_radius(12)
-                            .inner_margin(egui::vec2(8.0, 2.0))
-                            .show(ui, |ui| {
-                                ui.label(
-                                    RichText::new(&awareness.name).size(12.0).color(peer_color),
-                                );
-                            });
+                        let peer_color = peer_color(&hues, &awareness.endpoint_id);
+                        let chip = render_user_chip(
+                            ui,
+                            &awareness.profile,
+                            awareness.activity,
+                            peer_color,
+                        )
+                        .interact(egui::Sense::click())
+                        .on_hover_text(format!("Jump to {}'s cursor", awareness.profile.name));
+                        if chip.clicked() {
+                            jump_to = Some(awareness.endpoint_id);
+                        }
+
+                        let following = state.following == Some(awareness.endpoint_id);
+                        let follow_button = egui::Button::new(RichText::new("👁").size(11.0))
+                            .corner_radius(6)
+                            .selected(following);
+                        let follow_hover = if following {
+                            format!("Stop following {}", awareness.profile.name)
+                        } else {
+                            format!("Follow {} as they scroll", awareness.profile.name)
+                        };
+                        if ui.add(follow_button).on_hover_text(follow_hover).clicked() {
+                            toggle_follow = Some(awareness.endpoint_id);
+                        }
                     });
+
+                if jump_to.is_some() {
+                    state.jump_to = jump_to;
+                }
+                if let Some(endpoint_id) = toggle_follow {
+                    state.following = if state.following == Some(endpoint_id) {
+                        None
+                    } else {
+                        Some(endpoint_id)
+                    };
+                }
+
+                // Transient notices for peers that just left
+                for departed in &state.session.presence().recently_left {
+                    ui.add_space(6.0);
+                    ui.label(
+                        RichText::new(format!("{} left", departed.profile.name))
+                            .size(12.0)
+                            .italics()
+                            .color(egui::Color32::from_rgb(100, 116, 139)),
+                    );
+                }
             });
         });
 
         ui.add_space(24.0);
 
-        // Text editor in a styled frame
-        let editor_frame = egui::Frame::new()
-            .fill(egui::Color32::from_rgb(255, 255, 255))
-            .stroke(egui::Stroke::new(
-                1.0,
-                egui::Color32::from_rgb(226, 232, 240),
-            ))
-            .corner_radius(8)
-            .inner_margin(egui::vec2(16.0, 16.0));
+        ui.horizontal_top(|ui| {
+            render_document_sidebar(ui, state);
 
-        {
-            let doc_text = state.loro_doc.get_text("text");
-            let mut text_content = doc_text.to_string();
+            ui.add_space(12.0);
 
-            let text_edit_id = ui.id().with("text_edit");
+            ui.vertical(|ui| {
+                open_peer_document(state);
 
-            if state.egui_cursors_needs_update {
-                state.egui_cursors_needs_update = false;
-                update_egui_from_loro_cursors(ui, text_edit_id, &state.loro_doc, &state.cursors);
-            }
+                let text_edit_id = ui.id().with(("text_edit", &state.document));
+                let editor_focused = ui.memory(|mem| mem.has_focus(text_edit_id));
+
+                // Take undo/redo away from TextEdit so only our own Loro operations are reverted
+                if editor_focused {
+                    let (undo, redo) = ui.input_mut(|input| {
+                        let redo = input.consume_shortcut(&REDO_SHORTCUT)
+                            || input.consume_shortcut(&REDO_ALT_SHORTCUT);
+                        let undo = input.consume_shortcut(&UNDO_SHORTCUT);
+                        (undo, redo)
+                    });
 
-            let output = editor_frame
+                    let restored_cursors = if redo {
+                        state.local_undo.redo()
+                    } else if undo {
+                        state.local_undo.undo()
+                    } else {
+                        None
+                    };
+
+                    if let Some(cursors) = restored_cursors {
+                        let _ = state.session.set_cursors(cursors.clone());
+                        state.cursors = cursors;
+                        state.egui_cursors_needs_update = true;
+                    }
+                }
+
+                let loro_doc = state.session.loro_doc().clone();
+                let doc_text = workspace::document_text(&loro_doc, &state.document);
+                let mut text_content = doc_text.to_string();
+
+                if state.egui_cursors_needs_update {
+                    state.egui_cursors_needs_update = false;
+                    update_egui_from_loro_cursors(ui, text_edit_id, &loro_doc, &state.cursors);
+                }
+
+                let format = workspace::format(&loro_doc, &state.document);
+                let spans = rich_text::styled_spans(&doc_text);
+                ui.horizontal(|ui| {
+                    match format {
+                        DocumentFormat::RichText => {
+                            render_formatting(ui, state, text_edit_id, editor_focused, &spans)
+                        }
+                        DocumentFormat::Markdown => {
+                            let preview_button =
+                                egui::Button::new(RichText::new("👁 Preview").size(13.0))
+                                    .min_size(egui::vec2(80.0, 28.0))
+                                    .corner_radius(6)
+                                    .selected(state.markdown_preview);
+                            if ui
+                                .add(preview_button)
+                                .on_hover_text("Show the rendered document next to its source")
+                                .clicked()
+                            {
+                                state.markdown_preview = !state.markdown_preview;
+                            }
+                        }
+                    }
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        let markdown = format == DocumentFormat::Markdown;
+                        let markdown_button =
+                            egui::Button::new(RichText::new("Markdown").size(13.0))
+                                .min_size(egui::vec2(80.0, 28.0))
+                                .corner_radius(6)
+                                .selected(markdown);
+                        if ui
+                            .add(markdown_button)
+                            .on_hover_text(
+                                "Write this document in Markdown, for everyone in the session",
+                            )
+                            .clicked()
+                        {
+                            let format = if markdown {
+                                DocumentFormat::RichText
+                            } else {
+                                DocumentFormat::Markdown
+                            };
+                            let _ = state.session.set_document_format(&state.document, format);
+                        }
+                    });
+                });
+                render_link_edit(ui, state, text_edit_id);
+                // Formatting commits right away, so lay out what it produced
+                let spans = rich_text::styled_spans(&doc_text);
+
+                ui.add_space(8.0);
+
+                if format == DocumentFormat::Markdown && state.markdown_preview {
+                    let source = text_content.clone();
+                    ui.columns(2, |columns| {
+                        let (left, right) = columns.split_at_mut(1);
+                        render_editor(
+                            &mut left[0],
+                            state,
+                            text_edit_id,
+                            &doc_text,
+                            &mut text_content,
+                            &spans,
+                            format,
+                        );
+                        editor_frame().show(&mut right[0], |ui| {
+                            render_markdown_preview(
+                                ui,
+                                &source,
+                                ("markdown_preview", &state.document),
+                            );
+                        });
+                    });
+                } else {
+                    render_editor(
+                        ui,
+                        state,
+                        text_edit_id,
+                        &doc_text,
+                        &mut text_content,
+                        &spans,
+                        format,
+                    );
+                }
+            });
+        });
+    });
+
+    render_history_window(ui.ctx(), state);
+}
+
+/// The document's text edit in a scrolling frame, with peers' carets drawn over it.
+fn render_editor(
+    ui: &mut Ui,
+    state: &mut SessionState,
+    text_edit_id: egui::Id,
+    doc_text: &loro::LoroText,
+    text_content: &mut String,
+    spans: &[StyledSpan],
+    format: DocumentFormat,
+) {
+    let loro_doc = state.session.loro_doc().clone();
+    let scroll_target = resolve_scroll_target(state, &loro_doc);
+
+    let scroll_output = editor_frame()
+        .show(ui, |ui| {
+            egui::ScrollArea::vertical()
+                .id_salt(("editor_scroll", &state.document))
+                .max_height(ui.available_height())
                 .show(ui, |ui| {
                     let front_layer_id = LayerId::new(ui.layer_id().order, ui.id().with("front"));
                     ui.ctx().set_sublayer(ui.layer_id(), front_layer_id);
 
-                    ui.scope_builder(UiBuilder::new().layer_id(front_layer_id), |ui| {
-                        TextEdit::multiline(&mut text_content)
-                            .id(text_edit_id)
-                            .frame(false)
-                            .background_color(Color32::TRANSPARENT)
-                            .font(egui::FontId::new(18.0, egui::FontFamily::Proportional))
-                            .desired_width(f32::INFINITY)
-                            .desired_rows(20)
-                            .show(ui)
-                    })
-                    .inner
+                    let output = ui
+                        .scope_builder(UiBuilder::new().layer_id(front_layer_id), |ui| {
+                            let mut layouter =
+                                |ui: &Ui, buffer: &dyn egui::TextBuffer, wrap_width: f32| {
+                                    let mut job = match format {
+                                        DocumentFormat::RichText => {
+                                            layout_job(buffer.as_str(), spans, ui.visuals())
+                                        }
+                                        DocumentFormat::Markdown => {
+                                            highlight_job(ui.ctx(), buffer.as_str(), ui.visuals())
+                                        }
+                                    };
+                                    job.wrap.max_width = wrap_width;
+                                    ui.fonts_mut(|fonts| fonts.layout_job(job))
+                                };
+
+                            TextEdit::multiline(text_content)
+                                .id(text_edit_id)
+                                .frame(false)
+                                .background_color(Color32::TRANSPARENT)
+                                .layouter(&mut layouter)
+                                .desired_width(f32::INFINITY)
+                                .desired_rows(20)
+                                .show(ui)
+                        })
+                        .inner;
+
+                    if let Some((pos, align)) = scroll_target {
+                        let rect = output
+                            .galley
+                            .pos_from_cursor(CCursor::new(pos))
+                            .translate(output.galley_pos.to_vec2());
+                        ui.scroll_to_rect(rect, Some(align));
+                    }
+
+                    // First character in view, shared so others can follow us
+                    let visible_top = ui.clip_rect().top() - output.galley_pos.y;
+                    let viewport_top = output
+                        .galley
+                        .cursor_from_pos(egui::vec2(0.0, visible_top.max(0.0)))
+                        .index;
+
+                    (output, viewport_top, front_layer_id)
                 })
-                .inner;
+        })
+        .inner;
+    let viewport_rect = scroll_output.inner_rect;
+    let (output, viewport_top, front_layer_id) = scroll_output.inner;
 
-            if output.response.changed() {
-                let _ = doc_text.update(&text_content, Default::default());
-                state.loro_doc.commit();
-            }
+    if state.viewport_top != Some(viewport_top) {
+        state.viewport_top = Some(viewport_top);
+        let viewport = doc_text.get_cursor(viewport_top, loro::cursor::Side::Left);
+        state.session.set_viewport(viewport);
+    }
+
+    // Links open on a modified click, plain clicks keep placing the caret
+    if output.response.clicked()
+        && ui.input(|input| input.modifiers.command)
+        && let Some(pointer) = output.response.interact_pointer_pos()
+    {
+        let index = output
+            .galley
+            .cursor_from_pos(pointer - output.galley_pos)
+            .index;
+        if let Some(url) = link_at(spans, index) {
+            ui.ctx().open_url(egui::OpenUrl::new_tab(url));
+        }
+    }
+
+    if output.response.changed() {
+        // Editing takes the viewport back from whoever we were following
+        state.following = None;
+        state.local_undo.record_selection(&state.cursors);
+        let _ = state.session.set_text(&state.document, text_content);
+    }
+
+    if state.egui_cursors_needs_update {
+        state.egui_cursors_needs_update = false;
+    } else {
+        let new_cursors = get_loro_cursors_from_egui(&output, doc_text);
+        if new_cursors != state.cursors {
+            let _ = state.session.set_cursors(new_cursors.clone());
+            state.cursors = new_cursors;
+        }
+    }
+
+    render_peer_cursors(ui, &output, viewport_rect, front_layer_id, state, &loro_doc);
+}
+
+fn editor_frame() -> egui::Frame {
+    egui::Frame::new()
+        .fill(egui::Color32::from_rgb(255, 255, 255))
+        .stroke(egui::Stroke::new(
+            1.0,
+            egui::Color32::from_rgb(226, 232, 240),
+        ))
+        .corner_radius(8)
+        .inner_margin(egui::vec2(16.0, 16.0))
+}
+
+/// Formatting toolbar and shortcuts for the editor's selection.
+fn render_formatting(
+    ui: &mut Ui,
+    state: &mut SessionState,
+    text_edit_id: egui::Id,
+    editor_focused: bool,
+    spans: &[StyledSpan],
+) {
+    // The text edit keeps its selection after losing focus to a toolbar button
+    let selection = TextEdit::load_state(ui.ctx(), text_edit_id)
+        .and_then(|text_edit_state| text_edit_state.cursor.char_range())
+        .map(|range| range.as_sorted_char_range())
+        .unwrap_or(0..0);
+
+    // Show what the selection starts with, or what typing at the caret continues
+    let active = if selection.is_empty() {
+        attributes_at(spans, selection.start)
+    } else {
+        attributes_at(spans, selection.start + 1)
+    };
+
+    let mut action = render_format_toolbar(ui, &active);
+    if editor_focused && let Some(shortcut_action) = consume_format_shortcuts(ui) {
+        action = Some(shortcut_action);
+    }
 
-            if state.egui_cursors_needs_update {
-                state.egui_cursors_needs_update = false;
+    match action {
+        Some(FormatAction::Toggle(style)) => {
+            state.local_undo.record_selection(&state.cursors);
+            let _ = state
+                .session
+                .toggle_style(&state.document, selection, style);
+            ui.memory_mut(|mem| mem.request_focus(text_edit_id));
+        }
+        Some(FormatAction::Heading(level)) => {
+            state.local_undo.record_selection(&state.cursors);
+            let _ = state
+                .session
+                .toggle_heading(&state.document, selection, level);
+            ui.memory_mut(|mem| mem.request_focus(text_edit_id));
+        }
+        Some(FormatAction::Link) => {
+            // With nothing selected, edit the link the caret is in
+            let range = if selection.is_empty() {
+                spans
+                    .iter()
+                    .find(|span| {
+                        span.range.contains(&selection.start) && span.attributes.link.is_some()
+                    })
+                    .map(|span| span.range.clone())
             } else {
-                let new_cursors = get_loro_cursors_from_egui(&output, &doc_text);
-                if new_cursors != state.cursors {
-                    state.cursors = new_cursors;
-                    let _ = broadcast_awareness(state);
-                }
+                Some(selection)
+            };
+            if let Some(range) = range {
+                let url = link_at(spans, range.start).unwrap_or_default().to_string();
+                state.link_edit = Some(LinkEdit { range, url });
+                ui.memory_mut(|mem| mem.request_focus(link_url_id(text_edit_id)));
             }
+        }
+        None => {}
+    }
+}
+
+fn link_url_id(text_edit_id: egui::Id) -> egui::Id {
+    text_edit_id.with("link_url")
+}
+
+/// URL field for the link being set from the toolbar.
+fn render_link_edit(ui: &mut Ui, state: &mut SessionState, text_edit_id: egui::Id) {
+    let Some(link_edit) = &mut state.link_edit else {
+        return;
+    };
 
-            render_peer_cursors(ui, &output, &state.awareness_cache, &state.loro_doc);
+    let mut apply = None;
+    let mut close = false;
+    ui.add_space(6.0);
+    ui.horizontal(|ui| {
+        ui.label(
+            RichText::new("Link")
+                .size(13.0)
+                .color(egui::Color32::from_rgb(71, 85, 105)),
+        );
+
+        let response = ui.add(
+            TextEdit::singleline(&mut link_edit.url)
+                .id(link_url_id(text_edit_id))
+                .hint_text("https://")
+                .desired_width(320.0)
+                .margin(egui::vec2(6.0, 4.0)),
+        );
+
+        let url = link_edit.url.trim();
+        let entered = response.lost_focus() && ui.input(|input| input.key_pressed(Key::Enter));
+        let apply_button = egui::Button::new(RichText::new("Apply").size(13.0))
+            .min_size(egui::vec2(80.0, 28.0))
+            .corner_radius(6);
+        if (ui.add_enabled(!url.is_empty(), apply_button).clicked() || entered) && !url.is_empty() {
+            apply = Some(Some(url.to_string()));
+        }
+
+        let remove_button = egui::Button::new(RichText::new("Remove").size(13.0))
+            .min_size(egui::vec2(80.0, 28.0))
+            .corner_radius(6);
+        if ui.add(remove_button).clicked() {
+            apply = Some(None);
+        }
+
+        let cancel_button = egui::Button::new(RichText::new("Cancel").size(13.0))
+            .min_size(egui::vec2(80.0, 28.0))
+            .corner_radius(6);
+        if ui.add(cancel_button).clicked() || ui.input(|input| input.key_pressed(Key::Escape)) {
+            close = true;
         }
     });
+
+    if let Some(url) = apply {
+        state.local_undo.record_selection(&state.cursors);
+        let range = link_edit.range.clone();
+        let _ = state
+            .session
+            .set_link(&state.document, range, url.as_deref());
+        close = true;
+    }
+    if close {
+        state.link_edit = None;
+        ui.memory_mut(|mem| mem.request_focus(text_edit_id));
+    }
+}
+
+/// Name chip with an avatar circle showing the user's initials.
+fn render_user_chip(
+    ui: &mut Ui,
+    profile: &Profile,
+    activity: Activity,
+    color: Color32,
+) -> egui::Response {
+    // Users who are not at the keyboard fade out
+    let color = activity_color(activity, color);
+
+    egui::Frame::new()
+        .fill(egui::Color32::from_rgba_unmultiplied(
+            color.r(),
+            color.g(),
+            color.b(),
+            30,
+        ))
+        .stroke(egui::Stroke::new(1.0, color))
+        .corner_radius(12)
+        .inner_margin(egui::vec2(4.0, 2.0))
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.spacing_mut().item_spacing.x = 4.0;
+
+                let (avatar_rect, _) =
+                    ui.allocate_exact_size(egui::vec2(18.0, 18.0), egui::Sense::hover());
+                ui.painter().circle_filled(avatar_rect.center(), 9.0, color);
+                ui.painter().text(
+                    avatar_rect.center(),
+                    egui::Align2::CENTER_CENTER,
+                    profile.avatar_initials(),
+                    egui::FontId::proportional(9.0),
+                    Color32::WHITE,
+                );
+
+                ui.label(RichText::new(&profile.name).size(12.0).color(color));
+
+                let (indicator, indicator_color) = match activity {
+                    Activity::Active => ("●", Color32::from_rgb(22, 163, 74)),
+                    Activity::Typing => ("✎", color),
+                    Activity::Idle => ("●", Color32::from_rgb(217, 119, 6)),
+                    Activity::Away => ("○", Color32::from_rgb(100, 116, 139)),
+                };
+                ui.label(RichText::new(indicator).size(10.0).color(indicator_color))
+                    .on_hover_text(activity.label());
+                ui.add_space(4.0);
+            });
+        })
+        .response
+}
+
+fn activity_color(activity: Activity, color: Color32) -> Color32 {
+    match activity {
+        Activity::Active | Activity::Typing => color,
+        Activity::Idle | Activity::Away => {
+            Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), 120)
+        }
+    }
+}
+
+fn render_connection_status(ui: &mut Ui, status: ConnectionStatus) {
+    let (text, color, hover) = match status {
+        ConnectionStatus::Waiting => (
+            "● Waiting for peers",
+            Color32::from_rgb(100, 116, 139),
+            "Nobody else has connected yet",
+        ),
+        ConnectionStatus::Connected => (
+            "● Connected",
+            Color32::from_rgb(22, 163, 74),
+            "Edits are reaching the other peers",
+        ),
+        ConnectionStatus::Reconnecting => (
+            "● Reconnecting…",
+            Color32::from_rgb(217, 119, 6),
+            "Lost every peer, trying to rejoin. Edits are kept and sync once reconnected",
+        ),
+        ConnectionStatus::Offline => (
+            "● Offline",
+            Color32::from_rgb(220, 38, 38),
+            "Cannot reach any peer. Still retrying in the background",
+        ),
+    };
+
+    ui.label(RichText::new(text).size(12.0).color(color))
+        .on_hover_text(hover);
+}
+
+fn render_message_errors(ui: &mut Ui, message_errors: &MessageErrors) {
+    let total = message_errors.total();
+    if total == 0 {
+        return;
+    }
+
+    let details = message_errors
+        .counts
+        .iter()
+        .map(|(peer, count)| {
+            let blocked = if message_errors.blocked.contains(peer) {
+                ", blocked"
+            } else {
+                ""
+            };
+            format!("{}: {count}{blocked}", peer.fmt_short())
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    ui.label(
+        RichText::new(format!("⚠ {total} rejected"))
+            .size(12.0)
+            .color(Color32::from_rgb(217, 119, 6)),
+    )
+    .on_hover_text(format!(
+        "Messages that could not be read, by delivering peer:\n{details}"
+    ));
+}
+
+fn render_incompatible_peers(ui: &mut Ui, presence: &Presence) {
+    let incompatible = presence
+        .peer_protocols
+        .iter()
+        .filter(|(_, (protocol, _))| !protocol.is_compatible())
+        .map(|(endpoint_id, (protocol, _))| format!("{}: {protocol}", short_id(endpoint_id)))
+        .collect::<Vec<_>>();
+    if incompatible.is_empty() {
+        return;
+    }
+
+    ui.label(
+        RichText::new(format!("⚠ {} incompatible", incompatible.len()))
+            .size(12.0)
+            .color(Color32::from_rgb(220, 38, 38)),
+    )
+    .on_hover_text(format!(
+        "These peers run a build that cannot exchange edits with this one (protocol v{}). \
+         Everyone should update to the same release.\n{}",
+        rusttalk_core::protocol::PROTOCOL_VERSION,
+        incompatible.join("\n")
+    ));
+}
+
+/// Opens the document of the peer we are about to jump to or are following, when it
+/// is not the one shown.
+fn open_peer_document(state: &mut SessionState) {
+    let Some(endpoint_id) = state.jump_to.or(state.following) else {
+        return;
+    };
+    let document = state
+        .session
+        .presence()
+        .awareness_cache
+        .get(&endpoint_id)
+        .map(|(awareness, _)| awareness.document.clone());
+
+    if let Some(document) = document
+        && document != state.document
+        && workspace::exists(state.session.loro_doc(), &document)
+    {
+        switch_document(state, document);
+    }
+}
+
+/// Where to scroll the editor this frame: to a peer's cursor after clicking their chip,
+/// or to the top of their viewport while following them.
+fn resolve_scroll_target(
+    state: &mut SessionState,
+    loro_doc: &loro::LoroDoc,
+) -> Option<(usize, egui::Align)> {
+    let presence = state.session.presence();
+    if let Some(following) = state.following
+        && !presence.awareness_cache.contains_key(&following)
+    {
+        state.following = None;
+    }
+
+    let (endpoint_id, follow) = match state.jump_to.take() {
+        Some(endpoint_id) => (endpoint_id, false),
+        None => (state.following?, true),
+    };
+    let (awareness, _) = presence.awareness_cache.get(&endpoint_id)?;
+    if awareness.document != state.document {
+        return None;
+    }
+
+    if follow
+        && let Some(viewport) = &awareness.viewport
+        && let Ok(top) = loro_doc.get_cursor_pos(viewport)
+    {
+        return Some((top.current.pos, egui::Align::TOP));
+    }
+
+    let (primary, _) = awareness.loro_cursors.as_ref()?;
+    let caret = loro_doc.get_cursor_pos(primary).ok()?;
+    Some((caret.current.pos, egui::Align::Center))
 }
 
 fn update_egui_from_loro_cursors(
@@ -222,19 +826,13 @@ fn get_loro_cursors_from_egui(
     output: &egui::text_edit::TextEditOutput,
     doc_text: &loro::LoroText,
 ) -> LoroCursors {
-    let Some(cursor_range) = output.cursor_range else {
-        return None;
-    };
+    let cursor_range = output.cursor_range?;
 
     let primary_idx = cursor_range.primary.index;
     let secondary_idx = cursor_range.secondary.index;
 
-    let Some(primary) = doc_text.get_cursor(primary_idx, loro::cursor::Side::Left) else {
-        return None;
-    };
-    let Some(secondary) = doc_text.get_cursor(secondary_idx, loro::cursor::Side::Left) else {
-        return None;
-    };
+    let primary = doc_text.get_cursor(primary_idx, loro::cursor::Side::Left)?;
+    let secondary = doc_text.get_cursor(secondary_idx, loro::cursor::Side::Left)?;
 
     Some((primary.clone(), secondary.clone()))
 }
@@ -242,19 +840,36 @@ fn get_loro_cursors_from_egui(
 fn render_peer_cursors(
     ui: &mut egui::Ui,
     text_edit_output: &egui::text_edit::TextEditOutput,
-    awareness_cache: &crate::awareness::AwarenessCache,
+    viewport_rect: egui::Rect,
+    front_layer_id: LayerId,
+    state: &mut SessionState,
     loro_doc: &loro::LoroDoc,
 ) {
-    let painter = ui.painter_at(text_edit_output.text_clip_rect);
+    let painter = ui.painter_at(text_edit_output.text_clip_rect.intersect(viewport_rect));
+    // Flags go above the text so they stay readable
+    let flag_painter = ui.painter_at(viewport_rect).with_layer_id(front_layer_id);
     let galley = &text_edit_output.galley;
     let galley_pos = text_edit_output.galley_pos;
 
-    for (endpoint_id, (awareness, _)) in awareness_cache.iter() {
+    let hues = state.session.peer_hues();
+    let presence = state.session.presence();
+    let now = Instant::now();
+    let mut above = Vec::new();
+    let mut below = Vec::new();
+    state
+        .peer_caret_moves
+        .retain(|endpoint_id, _| presence.awareness_cache.contains_key(endpoint_id));
+
+    for (endpoint_id, (awareness, _)) in presence.awareness_cache.iter() {
+        if awareness.document != state.document {
+            continue;
+        }
+
         if let Some((cursor_primary, cursor_secondary)) = &awareness.loro_cursors
             && let Ok(primary) = loro_doc.get_cursor_pos(cursor_primary)
             && let Ok(secondary) = loro_doc.get_cursor_pos(cursor_secondary)
         {
-            let color = generate_peer_color(endpoint_id);
+            let color = activity_color(awareness.activity, peer_color(&hues, endpoint_id));
             let selection_color =
                 Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), 80);
 
@@ -271,12 +886,143 @@ fn render_peer_cursors(
                 .pos_from_cursor(CCursor::new(primary.current.pos))
                 .translate(galley_pos.to_vec2());
 
+            // Remember when each caret last moved, so flags of idle carets can hide
+            let moved_at = match state.peer_caret_moves.get(endpoint_id) {
+                Some(&(pos, moved_at)) if pos == primary.current.pos => moved_at,
+                _ => {
+                    state
+                        .peer_caret_moves
+                        .insert(*endpoint_id, (primary.current.pos, now));
+                    now
+                }
+            };
+
+            let name = awareness.profile.name.clone();
+            if cursor_rect.bottom() < viewport_rect.top() {
+                above.push((*endpoint_id, name, color));
+                continue;
+            }
+            if cursor_rect.top() > viewport_rect.bottom() {
+                below.push((*endpoint_id, name, color));
+                continue;
+            }
+
             paint_awareness_cursor(&painter, cursor_rect, color);
+
+            let flag_shown_for = now.duration_since(moved_at);
+            if flag_shown_for < CARET_FLAG_DURATION || awareness.activity == Activity::Typing {
+                let label = if awareness.activity == Activity::Typing {
+                    format!("{name} ✎")
+                } else {
+                    name
+                };
+                paint_caret_flag(&flag_painter, cursor_rect, viewport_rect, &label, color);
+                ui.ctx()
+                    .request_repaint_after(CARET_FLAG_DURATION.saturating_sub(flag_shown_for));
+            }
         }
     }
+    drop(presence);
+
+    let clicked_above = render_edge_markers(ui, viewport_rect, front_layer_id, &above, true);
+    let clicked_below = render_edge_markers(ui, viewport_rect, front_layer_id, &below, false);
+    if let Some(endpoint_id) = clicked_above.or(clicked_below) {
+        state.jump_to = Some(endpoint_id);
+    }
+}
+
+/// Name tag above a remote caret, or below it on the first visible line.
+fn paint_caret_flag(
+    painter: &egui::Painter,
+    cursor_rect: egui::Rect,
+    viewport_rect: egui::Rect,
+    label: &str,
+    color: Color32,
+) {
+    let galley = painter.layout_no_wrap(
+        label.to_string(),
+        egui::FontId::proportional(11.0),
+        Color32::WHITE,
+    );
+    let size = galley.size() + egui::vec2(8.0, 2.0);
+
+    let top = if cursor_rect.top() - size.y >= viewport_rect.top() {
+        cursor_rect.top() - size.y
+    } else {
+        cursor_rect.bottom()
+    };
+    let flag_rect = egui::Rect::from_min_size(egui::pos2(cursor_rect.center().x, top), size);
+
+    painter.rect_filled(flag_rect, 3.0, color);
+    painter.galley(flag_rect.min + egui::vec2(4.0, 1.0), galley, Color32::WHITE);
+}
+
+/// Clickable markers along the top or bottom edge of the editor for collaborators
+/// whose caret is scrolled out of view. Returns the peer whose marker was clicked.
+fn render_edge_markers(
+    ui: &mut Ui,
+    viewport_rect: egui::Rect,
+    front_layer_id: LayerId,
+    markers: &[(IdBytes, String, Color32)],
+    at_top: bool,
+) -> Option<IdBytes> {
+    let mut clicked = None;
+
+    // Markers go on the text's layer, after the text, so they win its clicks
+    ui.scope_builder(UiBuilder::new().layer_id(front_layer_id), |ui| {
+        let painter = ui.painter_at(viewport_rect);
+        let mut right = viewport_rect.right() - 8.0;
+
+        for (endpoint_id, name, color) in markers {
+            let arrow = if at_top { "▲" } else { "▼" };
+            let galley = painter.layout_no_wrap(
+                format!("{arrow} {name}"),
+                egui::FontId::proportional(11.0),
+                Color32::WHITE,
+            );
+            let size = galley.size() + egui::vec2(12.0, 4.0);
+            let top = if at_top {
+                viewport_rect.top() + 4.0
+            } else {
+                viewport_rect.bottom() - 4.0 - size.y
+            };
+            let marker_rect = egui::Rect::from_min_size(egui::pos2(right - size.x, top), size);
+            right = marker_rect.left() - 6.0;
+
+            let response = ui
+                .interact(
+                    marker_rect,
+                    ui.id().with(("edge_marker", endpoint_id)),
+                    egui::Sense::click(),
+                )
+                .on_hover_text(format!("Jump to {name}'s cursor"))
+                .on_hover_cursor(egui::CursorIcon::PointingHand);
+            if response.clicked() {
+                clicked = Some(*endpoint_id);
+            }
+
+            painter.rect_filled(marker_rect, 8.0, *color);
+            painter.galley(
+                marker_rect.min + egui::vec2(6.0, 2.0),
+                galley,
+                Color32::WHITE,
+            );
+        }
+    });
+
+    clicked
+}
+
+/// A peer's color from its assigned hue, falling back to the one derived from its id.
+pub fn peer_color(hues: &HashMap<IdBytes, u16>, endpoint_id: &IdBytes) -> Color32 {
+    hue_color(
+        hues.get(endpoint_id)
+            .copied()
+            .unwrap_or_else(|| profile::default_hue(endpoint_id)),
+    )
 }
 
-fn generate_peer_color(endpoint_id: &[u8; 32]) -> Color32 {
+pub fn hue_color(hue: u16) -> Color32 {
     fn hsl_to_rgb(h: f32, s: f32, l: f32) -> Color32 {
         let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
         let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
@@ -297,14 +1043,7 @@ fn generate_peer_color(endpoint_id: &[u8; 32]) -> Color32 {
             ((b + m) * 255.0) as u8,
         )
     }
-    // Generate hue from hash of full endpoint_id for better distribution
-    let mut hash: u32 = 2166136261;
-    for byte in endpoint_id {
-        hash ^= *byte as u32;
-        hash = hash.wrapping_mul(16777619);
-    }
-
-    let hue = (hash % 360) as f32; // 0-360 degrees
+    let hue = (hue % 360) as f32; // 0-360 degrees
     let saturation = 0.85; // High saturation for vibrant colors
     let lightness = 0.35; // Dark enough for white background (0-0.5 range)
 
@@ -1,137 +1,143 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, time::Instant};
 
-use anyhow::Result;
-use iroh::{Endpoint, protocol::Router};
-use iroh_gossip::{Gossip, TopicId, api::Event};
-use loro::LoroDoc;
+use rusttalk_core::{
+    Profile, Session, SessionEvent, SessionStart, SetupError, SetupOptions, SetupStage,
+    awareness::{IdBytes, LoroCursors},
+    identity, profile,
+    workspace::{DocumentId, MAIN_DOCUMENT},
+};
 use tokio::{
-    select,
-    sync::mpsc::{self, UnboundedSender},
+    sync::{
+        broadcast::{Receiver, error::RecvError},
+        oneshot, watch,
+    },
     task::JoinHandle,
-    time::interval,
 };
-use tokio_stream::StreamExt;
-
-use postcard::{from_bytes, to_allocvec as to_bytes};
 
 use crate::{
     App, State,
-    awareness::{AwarenessCache, IdBytes, LoroCursors, awareness_refresh},
-    gossip_message::{GossipMessage, handle_gossip_message},
+    history_panel::HistoryState,
+    local_undo::LocalUndo,
+    rich_text_format::LinkEdit,
+    screen_loading::LoadingState,
+    screen_lobby::{FailedStart, LobbyState},
 };
 
-pub async fn task_start_session(app: App, name: String, existing_peer: Option<String>) {
-    let old_state = app.replace_state(State::Loading);
+pub async fn task_start_session(app: App, profile: Profile, start: SessionStart) {
+    if let Err(err) = profile::save(&profile) {
+        eprintln!("Failed to save profile: {err:#}");
+    }
 
-    let Ok(session_state) = setup(&app, name, existing_peer).await else {
-        app.replace_state(old_state);
-        return;
+    let (progress_tx, progress_rx) = watch::channel(SetupStage::Binding);
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    let old_state = app.replace_state(State::Loading(LoadingState {
+        progress: Some(progress_rx),
+        cancel: Some(cancel_tx),
+        cancelling: false,
+    }));
+
+    let secret_key = identity::load_or_create()
+        .inspect_err(|err| eprintln!("Using a throwaway identity: {err:#}"))
+        .ok();
+    let options = SetupOptions {
+        progress: Some(progress_tx),
+        cancel: Some(cancel_rx),
+        secret_key,
+        ..Default::default()
+    };
+    let session = match Session::start_with(profile.clone(), start.clone(), options).await {
+        Ok(session) => session,
+        Err(error) => {
+            let mut lobby_state = match old_state {
+                State::Lobby(lobby_state) => lobby_state,
+                _ => LobbyState::new(),
+            };
+            lobby_state.failed_start = match error {
+                SetupError::Cancelled => None,
+                error => {
+                    eprintln!("Failed to start session: {error:?}");
+                    Some(Box::new(FailedStart {
+                        error,
+                        profile,
+                        start,
+                    }))
+                }
+            };
+            app.replace_state(State::Lobby(lobby_state));
+            return;
+        }
     };
 
-    app.replace_state(State::Session(session_state));
+    let events_handle = tokio::spawn(forward_session_events(app.clone(), session.subscribe()));
+    let local_undo = LocalUndo::new(session.loro_doc());
+
+    app.replace_state(State::Session(Box::new(SessionState {
+        session,
+        document: MAIN_DOCUMENT.to_string(),
+        renaming: None,
+        cursors: None,
+        egui_cursors_needs_update: false,
+        link_edit: None,
+        markdown_preview: true,
+        local_undo,
+        history: HistoryState::default(),
+        following: None,
+        jump_to: None,
+        viewport_top: None,
+        peer_caret_moves: HashMap::new(),
+        events_handle,
+    })));
 }
+
+/// GUI-side state around a running [`Session`].
 pub struct SessionState {
-    pub own_id: IdBytes,
-    pub own_name: String,
+    pub session: Session,
+
+    /// Workspace document shown in the editor.
+    pub document: DocumentId,
+    /// Document whose title is being edited in the sidebar, with the new title.
+    pub renaming: Option<(DocumentId, String)>,
 
     pub cursors: LoroCursors,
     pub egui_cursors_needs_update: bool,
-
-    pub loro_doc: LoroDoc,
-    pub loro_sub: loro::Subscription,
-
-    pub iroh_endpoint: Endpoint,
-    pub iroh_gossip: Gossip,
-    pub iroh_router: Router,
-
-    pub awareness_cache: AwarenessCache,
-    pub outbound_queue: OutboundQueue,
-    pub main_loop_handle: JoinHandle<Result<()>>,
+    /// Link being added or changed from the formatting toolbar.
+    pub link_edit: Option<LinkEdit>,
+    /// Whether Markdown documents show their rendered preview next to the source.
+    pub markdown_preview: bool,
+
+    pub local_undo: LocalUndo,
+    pub history: HistoryState,
+
+    /// Peer whose viewport we scroll along with.
+    pub following: Option<IdBytes>,
+    /// Peer whose cursor to scroll to on the next frame.
+    pub jump_to: Option<IdBytes>,
+    /// Index of the first visible character, as last shared with peers.
+    pub viewport_top: Option<usize>,
+    /// Where each remote caret was last seen and when it got there.
+    pub peer_caret_moves: HashMap<IdBytes, (usize, Instant)>,
+
+    pub events_handle: JoinHandle<()>,
 }
 
-pub type OutboundQueue = UnboundedSender<GossipMessage>;
-
-async fn setup(app: &App, name: String, existing_peer: Option<String>) -> Result<SessionState> {
-    const GOSSIP_MAX_MESSAGE_SIZE: usize = 2 * 1024 * 1024;
-    const TOPIC_ID_BYTES: [u8; 32] = [23u8; 32];
-
-    let iroh_endpoint = Endpoint::bind().await?;
-    let iroh_gossip = Gossip::builder()
-        .max_message_size(GOSSIP_MAX_MESSAGE_SIZE)
-        .spawn(iroh_endpoint.clone());
-    let iroh_router = Router::builder(iroh_endpoint.clone())
-        .accept(iroh_gossip::ALPN, iroh_gossip.clone())
-        .spawn();
-
-    let bootstrap_nodes = if let Some(existing_peer) = &existing_peer {
-        vec![existing_peer.parse()?]
-    } else {
-        vec![]
-    };
-
-    let mut gossip_topic = iroh_gossip
-        .subscribe(TopicId::from_bytes(TOPIC_ID_BYTES), bootstrap_nodes)
-        .await?;
-
-    if existing_peer.is_some() {
-        gossip_topic.joined().await?;
-    }
-
-    let (outbound_queue, mut outbound_queue_rx) = mpsc::unbounded_channel::<GossipMessage>();
-
-    let loro_doc = LoroDoc::new();
-    let loro_sub = {
-        let outbound_queue = outbound_queue.clone();
-        loro_doc.subscribe_local_update(Box::new(move |bytes| {
-            let _ = outbound_queue.send(GossipMessage::Update {
-                data: bytes.to_vec(),
-            });
-            true
-        }))
-    };
-
-    let main_loop_handle: JoinHandle<Result<()>> = tokio::spawn({
-        let mut app = app.clone();
-        let outbound_queue = outbound_queue.clone();
-        let loro_doc = loro_doc.clone();
-        let mut awareness_interval = interval(Duration::from_millis(500));
-        async move {
-            loop {
-                select! {
-                    Some(event) = gossip_topic.next() => {
-                        if let Ok(Event::Received(message)) = event {
-                            println!("Received message: {:?}", &message.content);
-                            let (_nonce, gossip_message): (u128, GossipMessage) = from_bytes(&message.content)?;
-                            handle_gossip_message(gossip_message, &mut app, &loro_doc, &outbound_queue)?;
-                        }
-                    }
-                    Some(message) = outbound_queue_rx.recv() => {
-                        let bytes = to_bytes(&(rand::random::<u128>(), &message))?;
-                        println!("Sending message: {:?}", bytes);
-                        gossip_topic.broadcast(bytes.into()).await?;
-                    }
-                    _ = awareness_interval.tick() => {
-                        awareness_refresh(&app)?;
-                    }
+/// Repaints when the session changes underneath the UI, and re-applies our own
+/// selection after remote edits have shifted the text around it.
+async fn forward_session_events(app: App, mut events: Receiver<SessionEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(SessionEvent::DocumentChanged) | Err(RecvError::Lagged(_)) => {
+                if let State::Session(session_state) = &mut *app.state.lock() {
+                    session_state.egui_cursors_needs_update = true;
                 }
             }
+            Ok(
+                SessionEvent::PresenceChanged
+                | SessionEvent::StatusChanged
+                | SessionEvent::MessageRejected
+                | SessionEvent::PeerLeft,
+            ) => {}
+            Err(RecvError::Closed) => return,
         }
-    });
-
-    outbound_queue.send(GossipMessage::RequestData)?;
-
-    Ok(SessionState {
-        own_id: iroh_endpoint.id().as_bytes().to_owned(),
-        own_name: name,
-        cursors: None,
-        egui_cursors_needs_update: false,
-        loro_doc,
-        loro_sub,
-        iroh_endpoint,
-        iroh_gossip,
-        iroh_router,
-        awareness_cache: HashMap::new(),
-        outbound_queue: outbound_queue,
-        main_loop_handle,
-    })
+        app.egui_ctx.request_repaint();
+    }
 }